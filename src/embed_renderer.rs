@@ -0,0 +1,157 @@
+use serde::{Deserialize, Serialize};
+use serenity::builder::CreateEmbed;
+
+use crate::eval_dice_expression::{format_aggregation, format_dice_rolls, format_result, Aggregation, DiceRolls};
+use crate::long_reply::MAX_MESSAGE_LENGTH;
+use crate::util::Outcome;
+
+// Embed colors for each outcome, and for rolls that have none
+const CRITICAL_SUCCESS_COLOR: u32 = 0xf1c40f;
+const SUCCESS_COLOR: u32 = 0x2ecc71;
+const FAILURE_COLOR: u32 = 0x95a5a6;
+const CRITICAL_FAILURE_COLOR: u32 = 0xe74c3c;
+const NEUTRAL_COLOR: u32 = 0x5865f2;
+
+// How dice expression replies are shown, chosen per guild with `dicebot output`
+#[derive(Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Renderer {
+    // plain markdown text
+    #[default]
+    Text,
+    // an embed with a field per repeat and a color for the outcome
+    Embed,
+}
+
+impl Renderer {
+    pub fn from_keyword(keyword: &str) -> Option<Renderer> {
+        match keyword.to_lowercase().as_str() {
+            "text" | "plain" | "default" => Some(Renderer::Text),
+            "embed" | "embeds" | "rich" => Some(Renderer::Embed),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Renderer::Text => "plain text",
+            Renderer::Embed => "embeds",
+        }
+    }
+}
+
+// A dice expression reply, ready to send: either text, or an embed with (optionally) some text above it
+pub struct RenderedRoll {
+    pub content: String,
+    pub embed: Option<CreateEmbed>,
+}
+
+impl RenderedRoll {
+    pub fn text(content: String) -> RenderedRoll {
+        RenderedRoll { content, embed: None }
+    }
+
+    // Put a label such as a macro's name in front of the roll
+    pub fn with_label(self, label: &str) -> RenderedRoll {
+        let content = match self.embed {
+            Some(_) => label.to_owned(),
+            None => format!("{label}: {}", self.content),
+        };
+        RenderedRoll { content, ..self }
+    }
+}
+
+// Rolls too long for a message go out as text even with embeds on, so that they can be attached as a file
+pub fn render_dice_rolls(rolls: &DiceRolls, renderer: Renderer) -> RenderedRoll {
+    let text = format_dice_rolls(rolls);
+    match renderer {
+        Renderer::Embed if text.chars().count() <= MAX_MESSAGE_LENGTH => RenderedRoll {
+            content: "".to_owned(),
+            embed: Some(render_embed(rolls)),
+        },
+        _ => RenderedRoll::text(text),
+    }
+}
+
+// Cut text down to one of Discord's embed limits
+fn truncate(text: String, max_chars: usize) -> String {
+    if text.chars().count() <= max_chars {
+        text
+    } else {
+        let mut truncated: String = text.chars().take(max_chars - 1).collect();
+        truncated.push('…');
+        truncated
+    }
+}
+
+// The color for a roll as a whole: a count succeeds if anything was counted,
+// otherwise the results have to agree on an outcome for it to show
+fn embed_color(rolls: &DiceRolls) -> u32 {
+    let outcome = match rolls.aggregation {
+        Aggregation::Count(..) if rolls.results.len() > 1 => {
+            if rolls.results.iter().any(|result| result.outcome == Some(Outcome::Success)) {
+                Some(Outcome::Success)
+            } else {
+                Some(Outcome::Failure)
+            }
+        }
+        _ => {
+            let first = rolls.results.first().and_then(|result| result.outcome);
+            if rolls.results.iter().all(|result| result.outcome == first) {
+                first
+            } else {
+                None
+            }
+        }
+    };
+    match outcome {
+        Some(Outcome::CriticalSuccess) => CRITICAL_SUCCESS_COLOR,
+        Some(Outcome::Success) => SUCCESS_COLOR,
+        Some(Outcome::Failure) => FAILURE_COLOR,
+        Some(Outcome::CriticalFailure) => CRITICAL_FAILURE_COLOR,
+        None => NEUTRAL_COLOR,
+    }
+}
+
+// Title with what was rolled, a field per repeat (or just a description for a single roll)
+// and the total in the footer
+fn render_embed(rolls: &DiceRolls) -> CreateEmbed {
+    let mut embed = CreateEmbed::default();
+    embed
+        .title(truncate(format!("Rolling {}", rolls.normalized), 256))
+        .color(embed_color(rolls));
+    match rolls.results.as_slice() {
+        [result] => {
+            embed.description(truncate(format_result(rolls, result), 4096));
+        }
+        results => {
+            for (i, result) in results.iter().enumerate() {
+                embed.field(format!("Roll {}", i + 1), truncate(format_result(rolls, result), 1024), true);
+            }
+        }
+    }
+    // footers don't render markdown
+    if let Some(aggregation_str) = format_aggregation(rolls) {
+        embed.footer(|footer| footer.text(aggregation_str.replace("**", "")));
+    }
+    embed
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::embed_renderer::{embed_color, truncate, NEUTRAL_COLOR, SUCCESS_COLOR};
+    use crate::eval_dice_expression::roll_dice_expression;
+    use crate::roll_under::RollUnderSystem;
+
+    #[test]
+    fn color_follows_outcome() {
+        let rolls = roll_dice_expression("1d20 <= 25", RollUnderSystem::Generic).unwrap().unwrap();
+        assert_eq!(embed_color(&rolls), SUCCESS_COLOR);
+        let rolls = roll_dice_expression("4d6*3", RollUnderSystem::Generic).unwrap().unwrap();
+        assert_eq!(embed_color(&rolls), NEUTRAL_COLOR);
+    }
+    #[test]
+    fn truncates_to_limit() {
+        assert_eq!(truncate("abc".to_owned(), 3), "abc");
+        assert_eq!(truncate("abcd".to_owned(), 3), "ab…");
+    }
+}
@@ -0,0 +1,189 @@
+use indoc::indoc;
+use regex::Regex;
+
+use crate::util::{find_oversized_number, format_modifier, format_roll, parse, roll, roll_with_advantage, too_big_number_error, too_many_dice_error, MarkCondition, Roll, MAX_DICE};
+
+// Evaluates an attack macro such as `attack +7 dmg 1d8+4` or `attack +5 adv vs 15 dmg 2d6+3`:
+// rolls the d20 to hit and then the damage, doubling the damage dice on a critical hit
+pub fn eval_attack(expression: &str) -> Option<String> {
+    lazy_static! {
+        // (indoc! removes leading whitespace at compile time)
+        static ref RE: Regex = Regex::new(indoc! {r"
+            (?xi) # case insensitive
+            ^
+            (?:attack|atk)
+            (?P<to_hit>[+-]\d+)?
+            (
+                (?P<disadvantage>dis(?:adv(?:antage)?)?(?P<disadvantage_rolls>\d+)?)
+                |
+                (?P<advantage>a(?:dv(?:antage)?)?(?P<advantage_rolls>\d+)?)
+                |
+                (?:c(?:rit)?(?P<crit_range>\d+))
+                |
+                (?P<max_crit>max(?:crit)?)
+                |
+                (?:vs(?:ac)?(?P<armor_class>\d+))
+                |
+                (?:
+                    (?:dmg|damage)
+                    (?P<num_dice>\d+)?
+                    d
+                    (?P<dice_size>\d+)
+                    (?P<damage_modifier>[+-]\d+)?
+                )
+            )*
+            $
+        "}).unwrap();
+    }
+
+    let expression = expression.replace(' ', "");
+    let groups = RE.captures(&expression)?;
+    groups.name("dice_size")?;
+    if let Some(number) = find_oversized_number(&groups) {
+        return Some(too_big_number_error(number));
+    }
+
+    let to_hit = parse(groups.name("to_hit"), 0);
+    // advantage and disadvantage cancel each other out if both are given
+    let has_disadvantage = groups.name("disadvantage").is_some();
+    let has_advantage = groups.name("advantage").is_some();
+    let disadvantage = has_disadvantage && !has_advantage;
+    let advantage = has_advantage && !has_disadvantage;
+    // e.g. `a3` for Elven Accuracy
+    let advantage_rolls = if advantage {
+        parse(groups.name("advantage_rolls"), 2).clamp(2, 10)
+    } else {
+        parse(groups.name("disadvantage_rolls"), 2).clamp(2, 10)
+    };
+    // e.g. `crit19` means a natural 19 or 20 is a critical hit
+    let crit_range = parse(groups.name("crit_range"), 20).clamp(2, 20);
+    let max_crit = groups.name("max_crit").is_some();
+    let armor_class = groups.name("armor_class").map(|ac| parse(Some(ac), 0));
+    let num_dice = parse(groups.name("num_dice"), 1).max(1);
+    if num_dice > MAX_DICE {
+        return Some(too_many_dice_error());
+    }
+    let dice_size = parse(groups.name("dice_size"), 6);
+    if dice_size < 1 {
+        return Some("Dice need at least one side.".to_owned());
+    }
+    let damage_modifier = parse(groups.name("damage_modifier"), 0);
+
+    let to_hit_str = format_modifier(to_hit as i64);
+    let damage_modifier_str = format_modifier(damage_modifier as i64);
+    let advantage_str = if disadvantage {
+        " with disadvantage".to_owned()
+    } else if advantage && advantage_rolls > 2 {
+        format!(" with advantage (best of {advantage_rolls})")
+    } else if advantage {
+        " with advantage".to_owned()
+    } else {
+        "".to_owned()
+    };
+    let armor_class_str = match armor_class {
+        Some(ac) => format!(" vs AC {ac}"),
+        None => "".to_owned(),
+    };
+    let crit_range_str = if crit_range < 20 {
+        format!(", critting on {crit_range}–20")
+    } else {
+        "".to_owned()
+    };
+    let max_crit_str = if max_crit {
+        ", maximizing crit dice"
+    } else {
+        ""
+    };
+    let normalized = format!(
+        "1d20{to_hit_str}{advantage_str}{armor_class_str}{crit_range_str}{max_crit_str}, {num_dice}d{dice_size}{damage_modifier_str} damage"
+    );
+
+    // Roll to hit
+    let (d20, d20_str) = if advantage || disadvantage {
+        let condition = if advantage {
+            MarkCondition::Highest
+        } else {
+            MarkCondition::Lowest
+        };
+        roll_with_advantage(20, 0, advantage_rolls, condition)
+    } else {
+        let d20 = roll(20, 0);
+        (d20.value, format_roll(&d20, false))
+    };
+    let attack_total = d20 as i64 + to_hit as i64;
+    let is_crit = d20 >= crit_range;
+    let is_fumble = d20 == 1;
+    let is_hit = match armor_class {
+        // natural 1s always miss and crits always hit, regardless of the target's AC
+        Some(ac) => !is_fumble && (is_crit || attack_total >= ac as i64),
+        None => !is_fumble,
+    };
+    let outcome_str = if is_crit {
+        " — **Critical hit!**"
+    } else if is_fumble {
+        " — **Critical miss!**"
+    } else if armor_class.is_none() {
+        ""
+    } else if is_hit {
+        " — Hit!"
+    } else {
+        " — Miss!"
+    };
+    let attack_str = format!("To hit: {d20_str}{to_hit_str} → **{attack_total}**{outcome_str}");
+
+    if !is_hit {
+        return Some(format!("Attacking with {normalized}:\n{attack_str}"));
+    }
+
+    let damage_str = roll_damage(num_dice, dice_size, damage_modifier, is_crit, max_crit);
+
+    Some(format!("Attacking with {normalized}:\n{attack_str}\n{damage_str}"))
+}
+
+// Rolls the damage for a hit, formatted as the reply's damage line.
+// On a crit, only the damage dice are doubled, never the modifier
+fn roll_damage(num_dice: i32, dice_size: i32, damage_modifier: i32, is_crit: bool, max_crit: bool) -> String {
+    let rolls: Vec<Roll> = (1..=num_dice).map(|_| roll(dice_size, 0)).collect();
+    let mut damage_parts: Vec<String> = rolls.iter().map(|roll| format_roll(roll, false)).collect();
+    // (i64 so that even 1000 maximized dice of i32::MAX sides can't overflow)
+    let mut damage = rolls.iter().fold(damage_modifier as i64, |acc, roll| acc + roll.value as i64);
+    if is_crit {
+        if max_crit {
+            // "Max dice + roll" variant: the extra dice count as their maximum face
+            damage_parts.extend((1..=num_dice).map(|_| dice_size.to_string()));
+            damage += num_dice as i64 * dice_size as i64;
+        } else {
+            let crit_rolls: Vec<Roll> = (1..=num_dice).map(|_| roll(dice_size, 0)).collect();
+            damage_parts.extend(crit_rolls.iter().map(|roll| format_roll(roll, false)));
+            damage += crit_rolls.iter().map(|roll| roll.value as i64).sum::<i64>();
+        }
+    }
+    let damage_parts_str = damage_parts.join(" + ");
+    let damage_modifier_str = format_modifier(damage_modifier as i64);
+    format!("Damage: {damage_parts_str}{damage_modifier_str} → **{damage}**")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::eval_attack::{eval_attack, roll_damage};
+
+    // d1s always roll 1, so the damage only depends on how many dice were rolled
+    #[test]
+    fn crits_double_damage_dice_but_not_the_modifier() {
+        assert_eq!(roll_damage(2, 1, 3, true, false), "Damage: ① + ① + ① + ① + 3 → **7**");
+    }
+    #[test]
+    fn max_crits_add_the_dice_maximum() {
+        assert_eq!(roll_damage(2, 1, 3, true, true), "Damage: ① + ① + 1 + 1 + 3 → **7**");
+    }
+    #[test]
+    fn regular_hits_roll_damage_once() {
+        assert_eq!(roll_damage(2, 1, 3, false, false), "Damage: ① + ① + 3 → **5**");
+        assert_eq!(roll_damage(2, 1, 3, false, true), "Damage: ① + ① + 3 → **5**");
+    }
+    #[test]
+    fn attacks_show_to_hit_and_damage() {
+        let result = eval_attack("atk +5 dmg 2d1+3").unwrap();
+        assert!(result.starts_with("Attacking with 1d20 + 5, 2d1 + 3 damage:\nTo hit: "));
+    }
+}
@@ -0,0 +1,149 @@
+use regex::Regex;
+
+use crate::util::{find_oversized_number, format_roll, mark_rolls, parse, roll, too_big_number_error, too_many_dice_error, MarkCondition, Roll, MAX_DICE};
+
+enum BladesRoll {
+    Action,
+    Fortune,
+    Resistance,
+    Engagement,
+}
+
+// Reads a pool of d6s: the highest die, or the lowest of the two rolled for a zero-dice pool.
+// Returns that die's value, whether it's a critical (two or more 6s), and which dice count towards the result
+fn read_pool(rolls: &[Roll], zero_dice: bool) -> (i32, bool, Vec<bool>) {
+    let sixes = rolls.iter().filter(|roll| roll.value == 6).count();
+    // a zero-dice pool can never be a critical
+    let is_critical = !zero_dice && sixes >= 2;
+    // every 6 counts towards a critical, so keep all of them in that case
+    let marked = if zero_dice {
+        mark_rolls(rolls, 1, MarkCondition::Lowest)
+    } else if is_critical {
+        rolls.iter().map(|roll| roll.value == 6).collect()
+    } else {
+        mark_rolls(rolls, 1, MarkCondition::Highest)
+    };
+    let result = rolls
+        .iter()
+        .zip(marked.iter())
+        .filter(|(_, is_marked)| **is_marked)
+        .map(|(roll, _)| roll.value)
+        .next()
+        .unwrap();
+    (result, is_critical, marked)
+}
+
+// Evaluates a Blades in the Dark roll such as `bitd 3`, `bitd fortune 2`, `bitd resist 1` or `bitd engage 2+1`:
+// rolls a pool of d6s and reads the highest one (or the lowest of 2d6 if the pool is empty)
+pub fn eval_blades(expression: &str) -> Option<String> {
+    lazy_static! {
+        static ref RE: Regex = Regex::new(
+            r"(?i)^(?:bitd|blades)\s*(?P<kind>act(?:ion)?|fortune|resist(?:ance)?|engage(?:ment)?)?\s*(?P<dice>\d+)(?:\s*\+\s*(?P<bonus_dice>\d+))?\s*$"
+        ).unwrap();
+    }
+
+    let groups = RE.captures(expression)?;
+    if let Some(number) = find_oversized_number(&groups) {
+        return Some(too_big_number_error(number));
+    }
+    let kind = match groups.name("kind").map(|kind| kind.as_str().to_lowercase()) {
+        Some(kind) if kind.starts_with("fortune") => BladesRoll::Fortune,
+        Some(kind) if kind.starts_with("resist") => BladesRoll::Resistance,
+        Some(kind) if kind.starts_with("engage") => BladesRoll::Engagement,
+        _ => BladesRoll::Action,
+    };
+    // bonus dice, e.g. from pushing yourself or a teammate's assist
+    let num_dice = parse(groups.name("dice"), 0) as i64 + parse(groups.name("bonus_dice"), 0) as i64;
+    if num_dice > MAX_DICE as i64 {
+        return Some(too_many_dice_error());
+    }
+
+    // With zero dice, roll two and take the lowest
+    let zero_dice = num_dice == 0;
+    let pool_size = if zero_dice { 2 } else { num_dice };
+    let rolls: Vec<Roll> = (0..pool_size).map(|_| roll(6, 0)).collect();
+    let (result, is_critical, marked) = read_pool(&rolls, zero_dice);
+    let roll_str = rolls
+        .iter()
+        .zip(marked.iter())
+        .map(|(roll, is_marked)| format_roll(roll, !is_marked))
+        .collect::<Vec<String>>()
+        .join(" + ");
+
+    let (kind_str, outcome_str) = match kind {
+        BladesRoll::Action => (
+            "action roll",
+            if is_critical {
+                "**Critical success!** You do it with increased effect.".to_owned()
+            } else if result == 6 {
+                "**Full success!** You do it.".to_owned()
+            } else if result >= 4 {
+                "**Partial success.** You do it, but there's a consequence.".to_owned()
+            } else {
+                "**Bad outcome.** Things go badly.".to_owned()
+            },
+        ),
+        BladesRoll::Fortune => (
+            "fortune roll",
+            if is_critical {
+                "**Critical:** exceptional result.".to_owned()
+            } else if result == 6 {
+                "**Full result.**".to_owned()
+            } else if result >= 4 {
+                "**Limited result.**".to_owned()
+            } else {
+                "**Poor result.**".to_owned()
+            },
+        ),
+        BladesRoll::Resistance => (
+            "resistance roll",
+            if is_critical {
+                "**Critical!** Clear 1 stress.".to_owned()
+            } else {
+                // the stress cost is 6 minus the highest die
+                let stress = 6 - result;
+                format!("Take **{stress}** stress.")
+            },
+        ),
+        BladesRoll::Engagement => (
+            "engagement roll",
+            if is_critical {
+                "**Critical:** controlled position, and you've already overcome the first obstacle.".to_owned()
+            } else if result == 6 {
+                "**Controlled** position.".to_owned()
+            } else if result >= 4 {
+                "**Risky** position.".to_owned()
+            } else {
+                "**Desperate** position.".to_owned()
+            },
+        ),
+    };
+
+    let normalized = if zero_dice {
+        format!("2d6, keeping lowest ({kind_str} with zero dice)")
+    } else {
+        format!("{num_dice}d6 ({kind_str})")
+    };
+    Some(format!("Rolling {normalized}:\n{roll_str} → **{result}**\n{outcome_str}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::eval_blades::{eval_blades, read_pool};
+    use crate::util::Roll;
+
+    #[test]
+    fn zero_dice_take_the_lowest_of_two() {
+        assert_eq!(read_pool(&[5, 2].map(Roll::new), true), (2, false, vec![false, true]));
+        // even two 6s aren't a critical with zero dice
+        assert_eq!(read_pool(&[6, 6].map(Roll::new), true).0, 6);
+        assert!(!read_pool(&[6, 6].map(Roll::new), true).1);
+        assert!(eval_blades("bitd 0").unwrap().starts_with("Rolling 2d6, keeping lowest (action roll with zero dice):"));
+    }
+    #[test]
+    fn double_sixes_are_critical() {
+        assert_eq!(read_pool(&[6, 3, 6].map(Roll::new), false), (6, true, vec![true, false, true]));
+        assert_eq!(read_pool(&[6, 3, 5].map(Roll::new), false), (6, false, vec![true, false, false]));
+        assert_eq!(read_pool(&[2, 4, 1].map(Roll::new), false), (4, false, vec![false, true, false]));
+    }
+}
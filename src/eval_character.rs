@@ -0,0 +1,232 @@
+use regex::Regex;
+
+use crate::util::{format_modifier, format_roll, mark_rolls, roll, MarkCondition, Roll};
+
+pub const ABILITIES: [&str; 6] = ["STR", "DEX", "CON", "INT", "WIS", "CHA"];
+
+// How to roll each of the six ability scores
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum GenerationMethod {
+    FourDropLowest,
+    FourRerollOnesDropLowest,
+    ThreeInOrder,
+    TwoPlusSix,
+}
+
+impl GenerationMethod {
+    pub fn from_keyword(keyword: &str) -> Option<GenerationMethod> {
+        match keyword.to_lowercase().replace(' ', "").as_str() {
+            "4d6k3" | "4d6kh3" | "4d6dl1" | "4d6d1" | "standard" => Some(GenerationMethod::FourDropLowest),
+            "4d6r1k3" | "4d6r1kh3" | "4d6r1dl1" | "4d6r1d1" | "heroic" => Some(GenerationMethod::FourRerollOnesDropLowest),
+            "3d6" | "classic" => Some(GenerationMethod::ThreeInOrder),
+            "2d6+6" => Some(GenerationMethod::TwoPlusSix),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            GenerationMethod::FourDropLowest => "4d6, dropping the lowest",
+            GenerationMethod::FourRerollOnesDropLowest => "4d6, rerolling 1s and dropping the lowest",
+            GenerationMethod::ThreeInOrder => "3d6 in order",
+            GenerationMethod::TwoPlusSix => "2d6+6",
+        }
+    }
+
+    // Roll one ability score, returning it along with the dice that made it
+    fn roll_score(self) -> (i32, String) {
+        let (rolls, keep, bonus): (Vec<Roll>, i32, i32) = match self {
+            GenerationMethod::FourDropLowest => ((0..4).map(|_| roll(6, 0)).collect(), 3, 0),
+            GenerationMethod::FourRerollOnesDropLowest => ((0..4).map(|_| roll(6, 1)).collect(), 3, 0),
+            GenerationMethod::ThreeInOrder => ((0..3).map(|_| roll(6, 0)).collect(), 3, 0),
+            GenerationMethod::TwoPlusSix => ((0..2).map(|_| roll(6, 0)).collect(), 2, 6),
+        };
+        let kept = mark_rolls(&rolls, keep, MarkCondition::Highest);
+        let score = rolls
+            .iter()
+            .zip(kept.iter())
+            .filter(|(_, is_kept)| **is_kept)
+            .map(|(roll, _)| roll.value)
+            .sum::<i32>()
+            + bonus;
+        let dice_str = rolls
+            .iter()
+            .zip(kept.iter())
+            .map(|(roll, is_kept)| format_roll(roll, !is_kept))
+            .collect::<Vec<String>>()
+            .join(" ");
+        (score, format!("{dice_str}{}", format_modifier(bonus as i64)))
+    }
+}
+
+pub fn ability_modifier(score: i32) -> i32 {
+    (score - 10).div_euclid(2)
+}
+
+// Format a modifier the way it's written on a character sheet, e.g. "+2", "+0" or "–1"
+pub fn format_ability_modifier(modifier: i32) -> String {
+    if modifier < 0 {
+        format!("–{}", -modifier)
+    } else {
+        format!("+{modifier}")
+    }
+}
+
+// Cost of a score under the 5e point-buy rules, where 8 is free and 15 costs 9.
+// Rolled scores can fall outside 8–15, so the table is extended (down to 3 and up to 18) to give a point-buy equivalent
+pub fn point_buy_cost(score: i32) -> i32 {
+    match score {
+        i32::MIN..=3 => -9,
+        4 => -6,
+        5 => -4,
+        6 => -2,
+        7 => -1,
+        8..=13 => score - 8,
+        14 => 7,
+        15 => 9,
+        16 => 12,
+        17 => 15,
+        _ => 19,
+    }
+}
+
+// Six rolled ability scores, in the order they were rolled
+pub struct AbilityScores {
+    pub method: GenerationMethod,
+    pub scores: Vec<i32>,
+    pub roll_strs: Vec<String>,
+}
+
+impl AbilityScores {
+    pub fn roll(method: GenerationMethod) -> AbilityScores {
+        let (scores, roll_strs) = (0..6).map(|_| method.roll_score()).unzip();
+        AbilityScores {
+            method,
+            scores,
+            roll_strs,
+        }
+    }
+
+    pub fn total_modifier(&self) -> i32 {
+        self.scores.iter().map(|score| ability_modifier(*score)).sum()
+    }
+
+    pub fn point_buy_total(&self) -> i32 {
+        self.scores.iter().map(|score| point_buy_cost(*score)).sum()
+    }
+}
+
+// Evaluates a character generation request such as `roll character`, `random char 3d6`
+// or `roll character heroic min +3` (rerolling the whole array until the modifiers add up to at least +3).
+// Returns the reply along with the scores, so that the player can go on to assign them
+pub fn eval_character(expression: &str) -> Option<Result<(String, AbilityScores), String>> {
+    lazy_static! {
+        static ref RE: Regex = Regex::new(
+            r"(?i)^(?:rand(?:om)?|roll)\s*char(?:acter)?\b\s*(?P<method>4d6r1(?:kh?3|dl?1)|4d6(?:kh?3|dl?1)|3d6|2d6\s*\+\s*6|standard|heroic|classic)?\s*(?:(?:min|reroll\s*below)\s*(?P<threshold>[+-]?\s*\d+))?\s*$"
+        ).unwrap();
+    }
+
+    let groups = RE.captures(expression)?;
+    let method = groups
+        .name("method")
+        .and_then(|method| GenerationMethod::from_keyword(method.as_str()))
+        .unwrap_or(GenerationMethod::FourDropLowest);
+    let threshold = match groups.name("threshold").map(|threshold| threshold.as_str().replace(' ', "").parse::<i32>()) {
+        Some(Ok(threshold)) => Some(threshold),
+        Some(Err(_)) => return Some(Err("That reroll threshold is too big a number.".to_owned())),
+        None => None,
+    };
+
+    let (reply, scores) = generate_character(method, threshold);
+    // the first time someone tries this, point out the other options
+    let hint_str = if groups.name("method").is_none() && threshold.is_none() {
+        "\n(Also try `roll character 4d6r1k3`, `3d6` or `2d6+6`, and `min +2` to reroll weak arrays)"
+    } else {
+        ""
+    };
+    Some(Ok((format!("{reply}{hint_str}"), scores)))
+}
+
+// Rolls a set of ability scores, rerolling the whole array while its total modifier is below the threshold
+pub fn generate_character(method: GenerationMethod, threshold: Option<i32>) -> (String, AbilityScores) {
+    // give up eventually if the threshold is out of reach
+    const MAX_ATTEMPTS: i32 = 100;
+    let mut attempts = 1;
+    let mut scores = AbilityScores::roll(method);
+    while threshold.is_some_and(|threshold| scores.total_modifier() < threshold) && attempts < MAX_ATTEMPTS {
+        scores = AbilityScores::roll(method);
+        attempts += 1;
+    }
+
+    let reply = format!(
+        "Rolling a character ({}):\n{}",
+        method.name(),
+        format_scores(&scores, threshold, attempts)
+    );
+    (reply, scores)
+}
+
+fn format_scores(scores: &AbilityScores, threshold: Option<i32>, attempts: i32) -> String {
+    let score_lines = scores
+        .scores
+        .iter()
+        .zip(scores.roll_strs.iter())
+        .enumerate()
+        .map(|(i, (score, roll_str))| {
+            let modifier = format_ability_modifier(ability_modifier(*score));
+            // 3d6 in order goes straight into the abilities, the other methods leave it up to the player
+            if scores.method == GenerationMethod::ThreeInOrder {
+                format!("{}: {roll_str} → **{score}** ({modifier})", ABILITIES[i])
+            } else {
+                format!("{roll_str} → **{score}** ({modifier})")
+            }
+        })
+        .collect::<Vec<String>>()
+        .join("\n");
+    let total_modifier = format_ability_modifier(scores.total_modifier());
+    let reroll_str = match threshold {
+        Some(threshold) if scores.total_modifier() < threshold => {
+            format!("\n(Couldn't reach a total modifier of {threshold} after {attempts} tries)")
+        }
+        Some(_) if attempts == 2 => "\n(Rerolled once to reach the threshold)".to_owned(),
+        Some(_) if attempts > 2 => format!("\n(Rerolled {} times to reach the threshold)", attempts - 1),
+        _ => "".to_owned(),
+    };
+    format!(
+        "{score_lines}\nTotal modifier: **{total_modifier}** · Point-buy equivalent: **{}**{reroll_str}",
+        scores.point_buy_total()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::eval_character::{ability_modifier, eval_character, point_buy_cost, GenerationMethod};
+
+    #[test]
+    fn modifiers_round_down() {
+        assert_eq!(ability_modifier(3), -4);
+        assert_eq!(ability_modifier(9), -1);
+        assert_eq!(ability_modifier(10), 0);
+        assert_eq!(ability_modifier(15), 2);
+        assert_eq!(ability_modifier(18), 4);
+    }
+    #[test]
+    fn only_character_requests_roll_characters() {
+        assert!(eval_character("roll charisma save").is_none());
+        assert!(eval_character("roll charisma").is_none());
+        assert!(eval_character("random characters are fun").is_none());
+        assert!(eval_character("roll character heroic min +3").unwrap().is_ok());
+        assert!(eval_character("Random char").unwrap().is_ok());
+    }
+    #[test]
+    fn standard_array_costs_27_points() {
+        assert_eq!([15, 14, 13, 12, 10, 8].iter().map(|score| point_buy_cost(*score)).sum::<i32>(), 27);
+    }
+    #[test]
+    fn method_keywords() {
+        assert_eq!(GenerationMethod::from_keyword("4d6kh3"), Some(GenerationMethod::FourDropLowest));
+        assert_eq!(GenerationMethod::from_keyword("2d6 + 6"), Some(GenerationMethod::TwoPlusSix));
+        assert_eq!(GenerationMethod::from_keyword("heroic"), Some(GenerationMethod::FourRerollOnesDropLowest));
+        assert_eq!(GenerationMethod::from_keyword("5d6"), None);
+    }
+}
@@ -0,0 +1,81 @@
+use regex::Regex;
+
+use crate::util::{find_oversized_number, format_modifier, format_roll, parse, roll, too_big_number_error, Roll};
+
+// Evaluates a Daggerheart duality roll such as `dh +3`, `dh -1 adv` or `dh +2 dis`:
+// rolls a Hope d12 and a Fear d12 and adds them together with the modifier.
+// Whichever die is higher decides whether the result is with Hope or with Fear, and matching dice are a critical.
+// Advantage adds a d6 and disadvantage subtracts one
+pub fn eval_daggerheart(expression: &str) -> Option<String> {
+    lazy_static! {
+        static ref RE: Regex = Regex::new(
+            r"(?i)^(?:dh|daggerheart)\s*(?:(?P<sign>[+-])\s*(?P<modifier>\d+))?\s*(?:(?P<advantage>adv(?:antage)?)|(?P<disadvantage>dis(?:advantage)?))?\s*$"
+        ).unwrap();
+    }
+
+    let groups = RE.captures(expression)?;
+    if let Some(number) = find_oversized_number(&groups) {
+        return Some(too_big_number_error(number));
+    }
+    let modifier = match groups.name("sign").map(|sign| sign.as_str()) {
+        Some("-") => -parse(groups.name("modifier"), 0),
+        _ => parse(groups.name("modifier"), 0),
+    };
+
+    let hope = roll(12, 0);
+    let fear = roll(12, 0);
+    let (advantage, advantage_str) = if groups.name("advantage").is_some() {
+        let die = roll(6, 0);
+        (die.value as i64, format!(" + {} (advantage)", format_roll(&die, false)))
+    } else if groups.name("disadvantage").is_some() {
+        let die = roll(6, 0);
+        (-die.value as i64, format!(" – {} (disadvantage)", format_roll(&die, false)))
+    } else {
+        (0, "".to_owned())
+    };
+    let total = hope.value as i64 + fear.value as i64 + advantage + modifier as i64;
+    let modifier_str = format_modifier(modifier as i64);
+
+    let (hope_str, fear_str, outcome_str) = format_duality(&hope, &fear);
+
+    let normalized = match advantage {
+        0 => format!("duality dice{modifier_str}"),
+        advantage if advantage > 0 => format!("duality dice{modifier_str} with advantage"),
+        _ => format!("duality dice{modifier_str} with disadvantage"),
+    };
+    Some(format!(
+        "Rolling {normalized}:\n{hope_str} (Hope) + {fear_str} (Fear){advantage_str}{modifier_str} → **{total}** {outcome_str}"
+    ))
+}
+
+// Formats the Hope and Fear dice and the outcome they give.
+// Like advantage, whichever die came out on top is highlighted
+fn format_duality(hope: &Roll, fear: &Roll) -> (String, String, &'static str) {
+    let (hope_str, fear_str) = (format_roll(hope, false), format_roll(fear, false));
+    if hope.value == fear.value {
+        (hope_str, fear_str, "— **Critical success!** Gain a Hope and clear a Stress.")
+    } else if hope.value > fear.value {
+        (format!("**{hope_str}**"), fear_str, "with Hope")
+    } else {
+        (hope_str, format!("**{fear_str}**"), "with Fear")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::eval_daggerheart::{eval_daggerheart, format_duality};
+    use crate::util::Roll;
+
+    #[test]
+    fn hope_and_fear() {
+        assert_eq!(format_duality(&Roll::new(9), &Roll::new(4)), ("**⑨**".to_owned(), "④".to_owned(), "with Hope"));
+        assert_eq!(format_duality(&Roll::new(2), &Roll::new(11)), ("②".to_owned(), "**⑪**".to_owned(), "with Fear"));
+    }
+    #[test]
+    fn matching_dice_are_critical() {
+        let (hope_str, fear_str, outcome_str) = format_duality(&Roll::new(7), &Roll::new(7));
+        assert_eq!((hope_str.as_str(), fear_str.as_str()), ("⑦", "⑦"));
+        assert_eq!(outcome_str, "— **Critical success!** Gain a Hope and clear a Stress.");
+        assert!(eval_daggerheart("dh +3 adv").unwrap().starts_with("Rolling duality dice + 3 with advantage:"));
+    }
+}
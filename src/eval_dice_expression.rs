@@ -1,11 +1,297 @@
-use std::cmp::{max, min};
+use std::cmp::max;
 
 use indoc::indoc;
 use regex::{Match, Regex};
 
-use crate::util::{format_roll, mark_rolls, roll, MarkCondition, Roll};
+use crate::roll_under::{roll_under_outcome, RollUnderSystem};
+use crate::util::{clamp_roll, find_oversized_number, format_modifier, format_roll, format_roll_summary, mark_rolls, parse, roll, roll_percentile, best_of, too_big_number_error, too_many_dice_error, Comparison, MarkCondition, Outcome, Roll, MAX_DICE};
 
-pub fn eval_dice_expression(expression: &str) -> Option<String> {
+// What was rolled (in words) along with the result of each repeat
+pub struct DiceRolls {
+    pub normalized: String,
+    pub results: Vec<RollResult>,
+    pub aggregation: Aggregation,
+    pub modifier: i64, // included in every result's sum
+    pub advantage_given: bool, // whether advantage or disadvantage was asked for, even if they cancelled out
+}
+
+pub struct RollResult {
+    pub result_str: String,
+    pub sum: i64,
+    pub outcome: Option<Outcome>, // for rolls that can succeed or fail
+}
+
+// How to summarize the results of a repeated roll
+#[derive(Copy, Clone)]
+pub enum Aggregation {
+    None,
+    Sum,
+    Lowest,
+    Highest,
+    Average,
+    Count(Comparison, i64), // how many results meet the target
+}
+
+#[derive(Copy, Clone)]
+enum SortOrder {
+    Ascending,
+    Descending,
+}
+
+// The result of a dice expression, before it's formatted
+pub enum DiceExpressionResult {
+    Rolls(DiceRolls),
+    // groups of expressions and errors are only ever shown as text, so they come already formatted
+    Text(String),
+}
+
+// default_roll_under is the rule system for roll-under rolls (e.g. `1d100 <= 45`) that don't name one
+pub fn eval_dice_expression(expression: &str, default_roll_under: RollUnderSystem) -> Option<String> {
+    match eval_dice_expression_result(expression, default_roll_under)? {
+        DiceExpressionResult::Rolls(rolls) => Some(format_dice_rolls(&rolls)),
+        DiceExpressionResult::Text(text) => Some(text),
+    }
+}
+
+// Like eval_dice_expression, but leaves the rolls unformatted so they can be rendered other ways (e.g. as an embed)
+pub fn eval_dice_expression_result(
+    expression: &str,
+    default_roll_under: RollUnderSystem,
+) -> Option<DiceExpressionResult> {
+    if let Some(result) = eval_group_expression(expression, default_roll_under) {
+        return Some(DiceExpressionResult::Text(result));
+    }
+    match roll_dice_expression(expression, default_roll_under)? {
+        Ok(rolls) => Some(DiceExpressionResult::Rolls(rolls)),
+        Err(error) => Some(DiceExpressionResult::Text(error)),
+    }
+}
+
+pub fn format_dice_rolls(rolls: &DiceRolls) -> String {
+    let total_str = match format_aggregation(rolls) {
+        Some(aggregation_str) => format!("\n{aggregation_str}"),
+        None => "".to_owned(),
+    };
+    let result_str = rolls
+        .results
+        .iter()
+        .map(|result| format_result(rolls, result))
+        .collect::<Vec<String>>()
+        .join("\n");
+    format!("Rolling {}:\n{result_str}{total_str}", rolls.normalized)
+}
+
+// A single result, ticked if it meets the target when counting
+pub fn format_result(rolls: &DiceRolls, result: &RollResult) -> String {
+    match rolls.aggregation {
+        Aggregation::Count(comparison, target) if comparison.matches(result.sum, target) => {
+            format!("{} ✓", result.result_str)
+        }
+        _ => result.result_str.clone(),
+    }
+}
+
+// Summarize the repeated rolls (by default, their grand total), if applicable
+pub fn format_aggregation(rolls: &DiceRolls) -> Option<String> {
+    let results = &rolls.results;
+    let sums = results.iter().map(|result| result.sum);
+    if results.len() <= 1 {
+        return None;
+    }
+    match rolls.aggregation {
+        Aggregation::None => None,
+        Aggregation::Sum => Some(format!("Total: **{}**", sums.sum::<i64>())),
+        Aggregation::Lowest => Some(format!("Lowest: **{}**", sums.min().unwrap())),
+        Aggregation::Highest => Some(format!("Highest: **{}**", sums.max().unwrap())),
+        Aggregation::Average => {
+            let average = sums.sum::<i64>() as f64 / results.len() as f64;
+            Some(format!("Average: **{average:.2}**"))
+        }
+        Aggregation::Count(comparison, target) => {
+            let count = sums.filter(|sum| comparison.matches(*sum, target)).count();
+            let num_results = results.len();
+            let symbol = comparison.symbol();
+            Some(format!("Rolls {symbol} {target}: **{count}** of {num_results}"))
+        }
+    }
+}
+
+// Spell out the arithmetic behind a roll: each result's dice and modifier, then how the results were combined
+pub fn format_breakdown(expression: &str, rolls: &DiceRolls) -> String {
+    let modifier_str = format_modifier(rolls.modifier);
+    let result_lines = rolls
+        .results
+        .iter()
+        .enumerate()
+        .map(|(i, result)| {
+            let dice_sum = result.sum - rolls.modifier;
+            let label = if rolls.results.len() > 1 {
+                format!("Roll {}: ", i + 1)
+            } else {
+                "".to_owned()
+            };
+            format!("{label}dice {dice_sum}{modifier_str} = **{}**", result.sum)
+        })
+        .collect::<Vec<String>>()
+        .join("\n");
+
+    let sums: Vec<i64> = rolls.results.iter().map(|result| result.sum).collect();
+    let sums_str = |separator: &str| sums.iter().map(|sum| sum.to_string()).collect::<Vec<String>>().join(separator);
+    let aggregation_str = if sums.len() > 1 {
+        match rolls.aggregation {
+            Aggregation::None => "".to_owned(),
+            Aggregation::Sum => format!("\nTotal: {} = **{}**", sums_str(" + "), sums.iter().sum::<i64>()),
+            Aggregation::Lowest => format!("\nLowest of {}: **{}**", sums_str(", "), sums.iter().min().unwrap()),
+            Aggregation::Highest => format!("\nHighest of {}: **{}**", sums_str(", "), sums.iter().max().unwrap()),
+            Aggregation::Average => {
+                let average = sums.iter().sum::<i64>() as f64 / sums.len() as f64;
+                format!("\nAverage: ({}) / {} = **{average:.2}**", sums_str(" + "), sums.len())
+            }
+            Aggregation::Count(comparison, target) => {
+                let symbol = comparison.symbol();
+                let matching: Vec<String> = sums
+                    .iter()
+                    .filter(|sum| comparison.matches(**sum, target))
+                    .map(|sum| sum.to_string())
+                    .collect();
+                let matching_str = if matching.is_empty() {
+                    "none".to_owned()
+                } else {
+                    matching.join(", ")
+                };
+                format!("\nRolls {symbol} {target}: {matching_str} → **{}**", matching.len())
+            }
+        }
+    } else {
+        "".to_owned()
+    };
+
+    format!("`{}` means {}:\n{result_lines}{aggregation_str}", expression.trim(), rolls.normalized)
+}
+
+// Evaluates a group of dice expressions such as `{4d6, 3d8, 2d10}kh1` or `{1d20+5, 1d20+2}kl1`,
+// where dropping/keeping applies to the total of each expression rather than to individual dice
+fn eval_group_expression(expression: &str, default_roll_under: RollUnderSystem) -> Option<String> {
+    lazy_static! {
+        // (indoc! removes leading whitespace at compile time)
+        static ref RE: Regex = Regex::new(indoc! {r"
+            (?xi) # case insensitive
+            ^
+            \{(?P<members>[^{}]+)\}
+            (
+                (?:dl?(?P<drop_lowest>\d+))
+                |
+                (?:dh(?P<drop_highest>\d+))
+                |
+                (?:kl(?P<keep_lowest>\d+))
+                |
+                (?:kh?(?P<keep_highest>\d+))
+            )?
+            $
+        "}).unwrap();
+    }
+
+    let expression = expression.replace(' ', "");
+    let groups = RE.captures(&expression)?;
+    let members = groups
+        .name("members")?
+        .as_str()
+        .split(',')
+        .map(|member| roll_dice_expression(member, default_roll_under).map(|rolls| rolls.map(|rolls| (member, rolls))))
+        .collect::<Option<Vec<Result<(&str, DiceRolls), String>>>>()?;
+    let members = match members.into_iter().collect::<Result<Vec<_>, String>>() {
+        Ok(members) => members,
+        Err(error) => return Some(error),
+    };
+    // each member counts as the total of its rolls, so it can't ask for them to be combined any other way
+    if let Some((member, _)) = members.iter().find(|(_, rolls)| !matches!(rolls.aggregation, Aggregation::Sum)) {
+        return Some(format!("`{member}` can't be in a group, as each expression in a group counts as its total."));
+    }
+    let totals: Vec<i64> = members
+        .iter()
+        .map(|(_, rolls)| rolls.results.iter().map(|result| result.sum).sum())
+        .collect();
+
+    // can't drop or keep every member of the group
+    let max_amount = members.len() as i32 - 1;
+    let drop_or_keep = if let Some(amount) = parse_option(groups.name("drop_lowest"), max_amount) {
+        Some((amount, MarkCondition::Lowest, true))
+    } else if let Some(amount) = parse_option(groups.name("drop_highest"), max_amount) {
+        Some((amount, MarkCondition::Highest, true))
+    } else if let Some(amount) = parse_option(groups.name("keep_lowest"), max_amount) {
+        Some((amount, MarkCondition::Lowest, false))
+    } else {
+        parse_option(groups.name("keep_highest"), max_amount)
+            .map(|amount| (amount, MarkCondition::Highest, false))
+    };
+
+    let (dropped, drop_or_keep_str) = match drop_or_keep {
+        Some((amount, condition, is_drop)) => {
+            let marked = mark_rolls(&totals, amount, condition);
+            let dropped: Vec<bool> = marked
+                .into_iter()
+                .map(|is_marked| if is_drop { is_marked } else { !is_marked })
+                .collect();
+            let action = if is_drop { "dropping" } else { "keeping" };
+            let condition = match condition {
+                MarkCondition::Highest => "highest",
+                MarkCondition::Lowest => "lowest",
+            };
+            let drop_or_keep_str = if amount == 1 {
+                format!(", {action} {condition} group")
+            } else {
+                format!(", {action} {condition} {amount} groups")
+            };
+            (dropped, drop_or_keep_str)
+        }
+        None => (vec![false; members.len()], "".to_owned()),
+    };
+
+    let normalized = members
+        .iter()
+        .map(|(member, _)| *member)
+        .collect::<Vec<&str>>()
+        .join(", ");
+    let result_str = members
+        .iter()
+        .zip(totals.iter())
+        .zip(dropped.iter())
+        .map(|(((member, rolls), total), is_dropped)| {
+            let label = if *is_dropped {
+                format!("~~`{member}`~~")
+            } else {
+                format!("`{member}`")
+            };
+            let member_result_str = rolls
+                .results
+                .iter()
+                .map(|result| result.result_str.as_str())
+                .collect::<Vec<&str>>()
+                .join(", ");
+            if rolls.results.len() > 1 {
+                format!("{label}: {member_result_str} = **{total}**")
+            } else {
+                format!("{label}: {member_result_str}")
+            }
+        })
+        .collect::<Vec<String>>()
+        .join("\n");
+    let total: i64 = totals
+        .iter()
+        .zip(dropped.iter())
+        .filter(|(_, is_dropped)| !**is_dropped)
+        .map(|(total, _)| total)
+        .sum();
+
+    Some(format!("Rolling {{{normalized}}}{drop_or_keep_str}:\n{result_str}\nTotal: **{total}**"))
+}
+
+// Roll a single dice expression such as `4d6r1k3*6`, without formatting the overall output.
+// Returns None if the expression isn't a dice expression at all,
+// or an error message if it is one but can't be rolled (e.g. a number is too big).
+// All sums are i64: with at most 1000 dice of at most i32::MAX sides, repeated at most 20 times,
+// no total can overflow
+pub fn roll_dice_expression(expression: &str, default_roll_under: RollUnderSystem) -> Option<Result<DiceRolls, String>> {
     lazy_static! {
         // (indoc! removes leading whitespace at compile time)
         static ref RE: Regex = Regex::new(indoc! {r"
@@ -15,7 +301,7 @@ pub fn eval_dice_expression(expression: &str) -> Option<String> {
             (?:
                 (?P<num_dice>\d+)?
                 d
-                (?P<dice_size>\d+)
+                (?P<dice_size>\d+|%)
             )?
             (
                 (?:dl?(?P<drop_lowest>\d+))
@@ -26,14 +312,36 @@ pub fn eval_dice_expression(expression: &str) -> Option<String> {
                 |
                 (?:kh?(?P<keep_highest>\d+))
                 |
-                (?P<disadvantage>d(?:is(?:adv(?:antage)?)?)?)
+                (?P<disadvantage>d(?:is(?:adv(?:antage)?)?(?P<disadvantage_rolls>\d+)?)?)
                 |
-                (?P<advantage>a(?:dv(?:antage)?)?)
+                (?P<advantage>a(?:dv(?:antage)?)?(?P<advantage_rolls>\d+)?)
                 |
                 ((?:\*|rep(?:eat)?)(?P<repeat2>\d+))
                 |
                 (?:r(?:eroll)?(?P<reroll>\d+))
                 |
+                (?:min(?P<min>\d+))
+                |
+                (?:max(?P<max>\d+))
+                |
+                (?:b(?:onus)?(?P<bonus>\d+))
+                |
+                (?:p(?:enalty)?(?P<penalty>\d+))
+                |
+                (?P<sort>s(?:ort)?(?:a|(?P<sort_descending>d))?)
+                |
+                (?P<unique>u(?:nique)?)
+                |
+                (?P<sort_results>rs(?:a|(?P<sort_results_descending>d))?)
+                |
+                (?P<aggregation>sum|total|lowest|highest|avg|average|mean|none|nototal)
+                |
+                (?:(?:<=|≤)(?P<roll_under>-?\d+))
+                |
+                (?P<roll_under_system>coc|cthulhu|gurps)
+                |
+                (?:count(?P<count_comparison>[<>=]=?|=[<>])(?P<count_target>-?\d+))
+                |
                 (?P<modifier>
                     [+-]
                     \d+
@@ -43,55 +351,142 @@ pub fn eval_dice_expression(expression: &str) -> Option<String> {
         "}).unwrap();
     }
 
-    fn parse(group_match: Option<Match>, default: i32) -> i32 {
-        match group_match {
-            Some(group_match) => group_match.as_str().parse::<i32>().unwrap_or(default),
-            None => default,
-        }
-    }
-    fn parse_option(group_match: Option<Match>, max_valid_value: i32) -> Option<i32> {
-        match group_match {
-            Some(group_match) => match group_match.as_str().parse::<i32>() {
-                Ok(num) if (1..=max_valid_value).contains(&num) => Some(num),
-                _ => None,
-            },
-            None => None,
-        }
-    }
-
     if let Some(groups) = RE.captures(&expression.replace(" ", "")) {
         if groups.name("dice_size").is_some() || groups.name("modifier").is_some() {
-            let mut num_dice: i32 = parse(groups.name("num_dice"), 1).clamp(1, 100);
-            let dice_size = parse(groups.name("dice_size"), 20);
-            let disadvantage = groups.name("disadvantage").is_some();
-            let advantage = groups.name("advantage").is_some();
+            if let Some(number) = find_oversized_number(&groups) {
+                return Some(Err(too_big_number_error(number)));
+            }
+            let num_dice: i32 = parse(groups.name("num_dice"), 1).max(1);
+            if num_dice > MAX_DICE {
+                return Some(Err(too_many_dice_error()));
+            }
+            let dice_size = match groups.name("dice_size") {
+                Some(dice_size) if dice_size.as_str() == "%" => 100,
+                dice_size => parse(dice_size, 20),
+            };
+            if dice_size < 1 {
+                return Some(Err("Dice need at least one side.".to_owned()));
+            }
+            // advantage and disadvantage cancel each other out if both are given
+            let has_disadvantage = groups.name("disadvantage").is_some();
+            let has_advantage = groups.name("advantage").is_some();
+            let disadvantage = has_disadvantage && !has_advantage;
+            let advantage = has_advantage && !has_disadvantage;
+            // how many times to roll the pool (e.g. `a3` for Elven Accuracy), keeping the best/worst total
+            let advantage_rolls = if advantage {
+                parse(groups.name("advantage_rolls"), 2).clamp(2, 10)
+            } else if disadvantage {
+                parse(groups.name("disadvantage_rolls"), 2).clamp(2, 10)
+            } else {
+                1
+            };
             // can't drop more than num_dice-1 or there's no dice left. similarly keeping any more than num_dice-1 doesn't make sense
             let drop_lowest = parse_option(groups.name("drop_lowest"), num_dice - 1);
             let drop_highest = parse_option(groups.name("drop_highest"), num_dice - 1);
             let keep_lowest = parse_option(groups.name("keep_lowest"), num_dice - 1);
             let keep_highest = parse_option(groups.name("keep_highest"), num_dice - 1);
-            let reroll = parse(groups.name("reroll"), 0);
+            // rerolling anything at or above the die's size would reroll every face
+            let reroll = parse(groups.name("reroll"), 0).min(dice_size);
+            // clamps only make sense if they actually cut off some of the die's faces
+            let min_clamp = parse_option(groups.name("min"), dice_size).filter(|min| *min > 1);
+            let max_clamp = parse_option(groups.name("max"), dice_size).filter(|max| *max < dice_size);
+            if let (Some(min), Some(max)) = (min_clamp, max_clamp) {
+                if min > max {
+                    return Some(Err(format!(
+                        "Can't treat rolls below {min} as {min} and above {max} as {max}, as {min} is more than {max}."
+                    )));
+                }
+            }
             let modifier = parse(groups.name("modifier"), 0);
-            let repeat1 = parse(groups.name("repeat1"), 1); // repeat syntax can be at beginning or end/with other options
-            let repeat2 = parse(groups.name("repeat2"), 1);
-            let repeat = max(repeat1, repeat2).clamp(1, 20);
-            if advantage || disadvantage {
-                num_dice = 1;
+            // A lone d100 is shown as its tens and units dice, Call of Cthulhu style.
+            // Bonus dice roll extra tens dice and keep the lowest, penalty dice keep the highest,
+            // and the two cancel each other out one for one
+            let percentile = dice_size == 100
+                && num_dice == 1
+                && !(advantage || disadvantage)
+                && reroll == 0
+                && min_clamp.is_none()
+                && max_clamp.is_none();
+            let has_bonus_dice = groups.name("bonus").is_some() || groups.name("penalty").is_some();
+            if has_bonus_dice && !percentile {
+                return Some(Err(
+                    "Bonus and penalty dice only work with a single d100 (no advantage, rerolls or clamps).".to_owned(),
+                ));
             }
-            let modifier_abs = modifier.abs();
-            let modifier_str = if modifier > 0 {
-                format!(" + {modifier}")
-            } else if modifier < 0 {
-                format!(" – {modifier_abs}")
+            let bonus_dice = (parse(groups.name("bonus"), 0) - parse(groups.name("penalty"), 0)).clamp(-9, 9);
+            let sort = if groups.name("sort_descending").is_some() {
+                Some(SortOrder::Descending)
+            } else if groups.name("sort").is_some() {
+                Some(SortOrder::Ascending)
             } else {
-                "".into()
+                None
             };
-            let advantage_str = if disadvantage {
-                " with disadvantage"
-            } else if advantage {
-                " with advantage"
+            // every die must be able to land on a different face for unique rolls to be possible
+            let distinct_faces = max_clamp.unwrap_or(dice_size) - min_clamp.unwrap_or(1) + 1;
+            let unique = groups.name("unique").is_some() && num_dice > 1;
+            if unique && num_dice > distinct_faces {
+                return Some(Err(format!(
+                    "Can't roll {num_dice} unique dice when there are only {distinct_faces} different faces."
+                )));
+            }
+            let sort_results = if groups.name("sort_results_descending").is_some() {
+                Some(SortOrder::Descending)
+            } else if groups.name("sort_results").is_some() {
+                Some(SortOrder::Ascending)
             } else {
-                ""
+                None
+            };
+            // roll-under target, e.g. a skill of 45 in `1d100 <= 45`
+            let roll_under = groups.name("roll_under").map(|target| {
+                let system = groups
+                    .name("roll_under_system")
+                    .and_then(|system| RollUnderSystem::from_keyword(system.as_str()))
+                    .unwrap_or(default_roll_under);
+                (system, parse(Some(target), 0) as i64)
+            });
+            let count_comparison = groups
+                .name("count_comparison")
+                .and_then(|comparison| Comparison::from_operator(comparison.as_str()));
+            let aggregation = match (count_comparison, groups.name("aggregation")) {
+                (Some(comparison), _) => Aggregation::Count(comparison, parse(groups.name("count_target"), 0) as i64),
+                (None, Some(aggregation)) => match aggregation.as_str().to_lowercase().as_str() {
+                    "lowest" => Aggregation::Lowest,
+                    "highest" => Aggregation::Highest,
+                    "avg" | "average" | "mean" => Aggregation::Average,
+                    "none" | "nototal" => Aggregation::None,
+                    _ => Aggregation::Sum,
+                },
+                // totals of roll-under rolls are meaningless, so only show them if asked for
+                (None, None) if roll_under.is_some() => Aggregation::None,
+                (None, None) => Aggregation::Sum,
+            };
+            let repeat1 = parse(groups.name("repeat1"), 1); // repeat syntax can be at beginning or end/with other options
+            let repeat2 = parse(groups.name("repeat2"), 1);
+            let repeat = max(repeat1, repeat2).max(1);
+            if repeat > 20 {
+                return Some(Err("Can't repeat a roll more than 20 times.".to_owned()));
+            }
+            let modifier_str = format_modifier(modifier as i64);
+            let advantage_str = {
+                let best_of_str = if advantage_rolls > 2 {
+                    format!(" (best of {advantage_rolls})")
+                } else {
+                    "".to_owned()
+                };
+                let worst_of_str = if advantage_rolls > 2 {
+                    format!(" (worst of {advantage_rolls})")
+                } else {
+                    "".to_owned()
+                };
+                if disadvantage {
+                    format!(" with disadvantage{worst_of_str}")
+                } else if advantage {
+                    format!(" with advantage{best_of_str}")
+                } else if has_advantage && has_disadvantage {
+                    " (advantage and disadvantage cancel out)".to_owned()
+                } else {
+                    "".to_owned()
+                }
             };
 
             enum DropOrKeep {
@@ -138,13 +533,16 @@ pub fn eval_dice_expression(expression: &str) -> Option<String> {
                     None => "",
                 };
                 match drop_or_keep_amount {
-                    Some(amount) if amount == 1 => format!(", {action} {condition} roll"),
+                    Some(1) => format!(", {action} {condition} roll"),
                     Some(amount) => format!(", {action} {condition} {amount} rolls"),
                     None => "".to_owned(),
                 }
             };
 
-            let reroll_str = if reroll > 0 {
+            // a few faces are listed, e.g. `1s/2s`, but longer runs are shown as a range so huge dice stay short
+            let reroll_str = if reroll > 3 {
+                format!(", rerolling 1s–{reroll}s")
+            } else if reroll > 0 {
                 let nums_string = (1..=reroll)
                     .map(|i| format!("{i}s"))
                     .collect::<Vec<String>>()
@@ -154,132 +552,210 @@ pub fn eval_dice_expression(expression: &str) -> Option<String> {
                 "".to_owned()
             };
 
+            let clamp_str = match (min_clamp, max_clamp) {
+                (Some(min), Some(max)) => format!(", treating rolls below {min} as {min} and above {max} as {max}"),
+                (Some(min), None) => format!(", treating rolls below {min} as {min}"),
+                (None, Some(max)) => format!(", treating rolls above {max} as {max}"),
+                (None, None) => "".to_owned(),
+            };
+
+            let sort_str = match sort {
+                Some(SortOrder::Ascending) => ", sorted ascending",
+                Some(SortOrder::Descending) => ", sorted descending",
+                None => "",
+            };
+            let unique_str = if unique {
+                ", rerolling duplicates"
+            } else {
+                ""
+            };
+
+            let bonus_dice_str = match bonus_dice {
+                1 => ", with 1 bonus die".to_owned(),
+                -1 => ", with 1 penalty die".to_owned(),
+                bonus if bonus > 1 => format!(", with {bonus} bonus dice"),
+                bonus if bonus < -1 => format!(", with {} penalty dice", -bonus),
+                _ => "".to_owned(),
+            };
+
+            let roll_under_str = match roll_under {
+                Some((RollUnderSystem::Generic, target)) => format!(", rolling under {target}"),
+                Some((system, target)) => format!(", rolling under {target} ({})", system.name()),
+                None => "".to_owned(),
+            };
+
             let repeat_str = if repeat > 1 {
-                format!(", repeating {repeat} times")
+                let sort_results_str = match sort_results {
+                    Some(SortOrder::Ascending) => ", sorted by total",
+                    Some(SortOrder::Descending) => ", sorted by total descending",
+                    None => "",
+                };
+                format!(", repeating {repeat} times{sort_results_str}")
             } else {
                 "".to_owned()
             };
 
             let normalized =
-                format!("{num_dice}d{dice_size}{modifier_str}{advantage_str}{reroll_str}{drop_or_keep_str}{repeat_str}");
+                format!("{num_dice}d{dice_size}{modifier_str}{advantage_str}{bonus_dice_str}{reroll_str}{clamp_str}{unique_str}{drop_or_keep_str}{sort_str}{roll_under_str}{repeat_str}");
 
-            struct Result {
-                result_str: String,
-                sum: i32
-            }
-            let aggregate_result = (0..repeat)
+            // Roll a single die, applying any rerolls and clamps
+            let roll_die = || clamp_roll(roll(dice_size, reroll), min_clamp, max_clamp);
+
+            // Roll the dice pool once, applying any rerolls, drops/keeps and sorting.
+            // Returns the formatted dice along with their sum (not including the modifier)
+            let roll_pool = || -> (String, i64) {
+                let mut rolls: Vec<Roll> = Vec::new();
+                for _ in 0..num_dice {
+                    let mut die = roll_die();
+                    // For unique rolls, reroll until the die doesn't match an earlier one,
+                    // remembering every face along the way so they can be shown struck through
+                    let mut duplicate_values = vec![];
+                    while unique && rolls.iter().any(|other| other.value == die.value) {
+                        duplicate_values.extend(die.old_value.iter().chain(die.unclamped_value.iter()));
+                        duplicate_values.push(die.value);
+                        die = roll_die();
+                    }
+                    die.duplicate_values = duplicate_values;
+                    rolls.push(die);
+                }
+
+                let marked = match drop_or_keep_amount {
+                    Some(amount) => mark_rolls(&rolls, amount, mark_condition.unwrap()),
+                    None => vec![false; rolls.len()],
+                };
+                let mut dice: Vec<(Roll, bool)> = rolls
+                    .into_iter()
+                    .zip(marked)
+                    .map(|(roll, is_marked)| {
+                        let is_dropped = match drop_or_keep {
+                            Some(DropOrKeep::Drop) => is_marked,
+                            Some(DropOrKeep::Keep) => !is_marked,
+                            None => false,
+                        };
+                        (roll, is_dropped)
+                    })
+                    .collect();
+                // sorting only affects the display, so it happens after the drops/keeps are decided
+                match sort {
+                    Some(SortOrder::Ascending) => dice.sort_by(|(a, _), (b, _)| a.cmp(b)),
+                    Some(SortOrder::Descending) => dice.sort_by(|(a, _), (b, _)| b.cmp(a)),
+                    None => {}
+                }
+
+                // Listing every die of a big pool is unreadable, so summarize it by face instead
+                let roll_str = if dice.len() > 50 {
+                    let (kept, dropped): (Vec<_>, Vec<_>) =
+                        dice.iter().partition(|(_, is_dropped)| !is_dropped);
+                    let kept: Vec<&Roll> = kept.into_iter().map(|(roll, _)| roll).collect();
+                    let dropped: Vec<&Roll> = dropped.into_iter().map(|(roll, _)| roll).collect();
+                    if dropped.is_empty() {
+                        format_roll_summary(&kept, false)
+                    } else {
+                        let kept_str = format_roll_summary(&kept, false);
+                        let dropped_str = format_roll_summary(&dropped, true);
+                        format!("{kept_str}, {dropped_str}")
+                    }
+                } else {
+                    dice.iter()
+                        .map(|(roll, is_dropped)| format_roll(roll, *is_dropped))
+                        .collect::<Vec<String>>()
+                        .join(" + ")
+                };
+                let sum = dice
+                    .iter()
+                    .filter(|(_, is_dropped)| !is_dropped)
+                    .fold(0, |acc, (roll, _)| acc + roll.value as i64);
+                (roll_str, sum)
+            };
+
+            let mut results: Vec<RollResult> = (0..repeat)
                 .map(|_idx| {
-                    if advantage || disadvantage {
-                        let roll1 = roll(dice_size, reroll);
-                        let roll2 = roll(dice_size, reroll);
-
-                        let roll1_str = format_roll(&roll1, false);
-                        let roll2_str = format_roll(&roll2, false);
-
-                        let full_roll_str = if roll1.value == roll2.value {
-                            format!("{roll1_str} / {roll2_str}")
-                        } else if (advantage && roll1.value > roll2.value)
-                            || (disadvantage && roll1.value < roll2.value)
-                        {
-                            format!("**{roll1_str}** / {roll2_str}")
+                    if percentile {
+                        let (value, roll_str) = roll_percentile(bonus_dice);
+                        let sum = modifier as i64 + value as i64;
+                        RollResult {
+                            result_str: format!("{roll_str}{modifier_str} → **{sum}**"),
+                            sum,
+                            outcome: None,
+                        }
+                    } else if advantage || disadvantage {
+                        let condition = if advantage {
+                            MarkCondition::Highest
                         } else {
-                            format!("{roll1_str} / **{roll2_str}**")
+                            MarkCondition::Lowest
                         };
-
-                        let sum = modifier
-                            + if advantage {
-                                max(roll1.value, roll2.value)
+                        // Roll the whole pool several times and keep the best (or worst) total
+                        let (kept_sum, full_roll_str) = best_of(advantage_rolls, condition, || {
+                            let (roll_str, dice_sum) = roll_pool();
+                            if num_dice > 1 {
+                                (dice_sum, format!("({roll_str})"))
                             } else {
-                                min(roll1.value, roll2.value)
-                            };
+                                (dice_sum, roll_str)
+                            }
+                        });
+                        let sum = modifier as i64 + kept_sum;
 
-                        Result {
+                        RollResult {
                             result_str: format!("{full_roll_str}{modifier_str} → **{sum}**"),
-                            sum: sum
+                            sum,
+                            outcome: None,
                         }
                     } else if modifier == 0 && num_dice == 1 {
-                        let roll = roll(dice_size, reroll);
-                        Result {
+                        let roll = roll_die();
+                        RollResult {
                             result_str: format_roll(&roll, false),
-                            sum: roll.value
+                            sum: roll.value as i64,
+                            outcome: None,
                         }
                     } else {
-                        let rolls: Vec<Roll> =
-                            (1..=num_dice).map(|_| roll(dice_size, reroll)).collect();
-                        let (roll_str, sum) = if let Some(drop_or_keep_amount) = drop_or_keep_amount
-                        {
-                            let marked =
-                                mark_rolls(&rolls, drop_or_keep_amount, mark_condition.unwrap());
-                            let roll_str = rolls
-                                .iter()
-                                .zip(marked.iter())
-                                .map(|(roll, is_marked)| {
-                                    format_roll(
-                                        roll,
-                                        match drop_or_keep.as_ref().unwrap() {
-                                            DropOrKeep::Drop => *is_marked,
-                                            DropOrKeep::Keep => !*is_marked,
-                                        },
-                                    )
-                                })
-                                .collect::<Vec<String>>()
-                                .join(" + ");
-                            let sum = rolls.iter().zip(marked.iter()).fold(
-                                modifier,
-                                |acc, (roll, is_marked)| match drop_or_keep.as_ref().unwrap() {
-                                    DropOrKeep::Drop => {
-                                        if *is_marked {
-                                            acc
-                                        } else {
-                                            acc + roll.value
-                                        }
-                                    }
-                                    DropOrKeep::Keep => {
-                                        if *is_marked {
-                                            acc + roll.value
-                                        } else {
-                                            acc
-                                        }
-                                    }
-                                },
-                            );
-                            (roll_str, sum)
-                        } else {
-                            let roll_str = rolls
-                                .iter()
-                                .map(|roll| format_roll(roll, false))
-                                .collect::<Vec<String>>()
-                                .join(" + ");
-                            let sum = rolls.iter().fold(modifier, |acc, roll| acc + roll.value);
-                            (roll_str, sum)
-                        };
-                        Result {
+                        let (roll_str, dice_sum) = roll_pool();
+                        let sum = modifier as i64 + dice_sum;
+                        RollResult {
                             result_str: format!("{roll_str}{modifier_str} → **{sum}**"),
-                            sum: sum
+                            sum,
+                            outcome: None,
                         }
                     }
-                }).reduce(|mut a, b| {
-                    // Aggregate the results
-                    Result {
-                        result_str: {
-                            a.result_str.push('\n');
-                            a.result_str.push_str(&b.result_str);
-                            a.result_str
-                        },
-                        sum: a.sum + b.sum
-                    }
-                }).unwrap();
-
-            // Show the grand total of all the repeated rolls, if applicable
-            let total_str = if repeat > 1 {
-                let total: i32 = aggregate_result.sum;
-                format!("\nTotal: **{total}**")
-            } else {
-                "".to_owned()
-            };
+                })
+                .collect();
+            // Judge each result against the roll-under target, if any
+            if let Some((system, target)) = roll_under {
+                for result in results.iter_mut() {
+                    let (outcome, outcome_str) = roll_under_outcome(system, result.sum, target);
+                    result.result_str = format!("{} — {outcome_str}", result.result_str);
+                    result.outcome = Some(outcome);
+                }
+            } else if let Aggregation::Count(comparison, target) = aggregation {
+                for result in results.iter_mut() {
+                    result.outcome = Some(if comparison.matches(result.sum, target) {
+                        Outcome::Success
+                    } else {
+                        Outcome::Failure
+                    });
+                }
+            } else if dice_size == 20 && num_dice == 1 {
+                // a natural 20 or natural 1 on a lone d20 (the kept one, with advantage)
+                for result in results.iter_mut() {
+                    result.outcome = match result.sum - modifier as i64 {
+                        20 => Some(Outcome::CriticalSuccess),
+                        1 => Some(Outcome::CriticalFailure),
+                        _ => None,
+                    };
+                }
+            }
+            match sort_results {
+                Some(SortOrder::Ascending) => results.sort_by_key(|result| result.sum),
+                Some(SortOrder::Descending) => results.sort_by_key(|result| std::cmp::Reverse(result.sum)),
+                None => {}
+            }
 
-            let result_str = aggregate_result.result_str;
-            Some(format!("Rolling {normalized}:\n{result_str}{total_str}"))
+            Some(Ok(DiceRolls {
+                normalized,
+                results,
+                aggregation,
+                modifier: modifier as i64,
+                advantage_given: has_advantage || has_disadvantage,
+            }))
         } else {
             None
         }
@@ -287,3 +763,66 @@ pub fn eval_dice_expression(expression: &str) -> Option<String> {
         None
     }
 }
+
+fn parse_option(group_match: Option<Match>, max_valid_value: i32) -> Option<i32> {
+    match group_match {
+        Some(group_match) => match group_match.as_str().parse::<i32>() {
+            Ok(num) if (1..=max_valid_value).contains(&num) => Some(num),
+            _ => None,
+        },
+        None => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::eval_dice_expression::{eval_dice_expression, roll_dice_expression};
+    use crate::roll_under::RollUnderSystem;
+
+    #[test]
+    fn impossible_unique_rolls_are_errors() {
+        let result = eval_dice_expression("5d4u+2", RollUnderSystem::Generic);
+        assert_eq!(result, Some("Can't roll 5 unique dice when there are only 4 different faces.".to_owned()));
+        assert!(roll_dice_expression("4d4u+2", RollUnderSystem::Generic).unwrap().is_ok());
+    }
+    #[test]
+    fn long_rerolls_are_shown_as_a_range() {
+        let rolls = roll_dice_expression("1d2147483647 r2147483647", RollUnderSystem::Generic).unwrap().unwrap();
+        assert_eq!(rolls.normalized, "1d2147483647, rerolling 1s–2147483647s");
+        let rolls = roll_dice_expression("1d6 r2", RollUnderSystem::Generic).unwrap().unwrap();
+        assert_eq!(rolls.normalized, "1d6, rerolling 1s/2s");
+    }
+    #[test]
+    fn min_clamp_above_max_clamp_is_an_error() {
+        let error = Some("Can't treat rolls below 15 as 15 and above 5 as 5, as 15 is more than 5.".to_owned());
+        assert_eq!(eval_dice_expression("1d20 min15 max5", RollUnderSystem::Generic), error);
+        assert_eq!(eval_dice_expression("3d20 min15 max5 u", RollUnderSystem::Generic), error);
+        let rolls = roll_dice_expression("1d20 min5 max5", RollUnderSystem::Generic).unwrap().unwrap();
+        assert_eq!(rolls.results[0].sum, 5);
+    }
+    #[test]
+    fn aggregations_do_not_clash_with_clamps() {
+        let lowest = eval_dice_expression("1d20*3 lowest", RollUnderSystem::Generic).unwrap();
+        assert!(lowest.contains("\nLowest: **"));
+        let clamped = eval_dice_expression("1d20min3", RollUnderSystem::Generic).unwrap();
+        assert!(clamped.starts_with("Rolling 1d20, treating rolls below 3 as 3:"));
+        assert_eq!(eval_dice_expression("1d20*3min", RollUnderSystem::Generic), None);
+    }
+    #[test]
+    fn groups_reject_other_aggregations() {
+        let result = eval_dice_expression("{1d20*2 highest, 1d6}", RollUnderSystem::Generic).unwrap();
+        assert_eq!(result, "`1d20*2highest` can't be in a group, as each expression in a group counts as its total.");
+        assert!(eval_dice_expression("{1d20*2, 1d6}kh1", RollUnderSystem::Generic).unwrap().ends_with("**"));
+    }
+    #[test]
+    fn bonus_dice_need_a_single_d100() {
+        let result = eval_dice_expression("1d100 b1", RollUnderSystem::Generic).unwrap();
+        assert!(result.starts_with("Rolling 1d100, with 1 bonus die:"));
+        let result = eval_dice_expression("d% p2 + 5", RollUnderSystem::Generic).unwrap();
+        assert!(result.starts_with("Rolling 1d100 + 5, with 2 penalty dice:"));
+        let error = Some("Bonus and penalty dice only work with a single d100 (no advantage, rerolls or clamps).".to_owned());
+        assert_eq!(eval_dice_expression("1d20 b1", RollUnderSystem::Generic), error);
+        assert_eq!(eval_dice_expression("2d100 p1", RollUnderSystem::Generic), error);
+        assert_eq!(eval_dice_expression("1d100 adv b1", RollUnderSystem::Generic), error);
+    }
+}
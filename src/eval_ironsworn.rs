@@ -0,0 +1,103 @@
+use regex::Regex;
+
+use crate::util::{find_oversized_number, format_modifier, format_roll, parse, roll, too_big_number_error};
+
+// Evaluates an Ironsworn roll: an action roll such as `ironsworn +2` (a d6 action die plus the stat and adds)
+// or a progress roll such as `ironsworn progress 7`, each against two d10 challenge dice
+pub fn eval_ironsworn(expression: &str) -> Option<String> {
+    lazy_static! {
+        static ref RE: Regex = Regex::new(
+            r"(?i)^(?P<prefix>ironsworn|iron)\s*(?:(?P<progress>progress\s*(?P<progress_score>\d+))|(?P<modifier>[+-]\s*\d+(?:\s*[+-]\s*\d+)*)?)\s*$"
+        ).unwrap();
+        static ref TERM_RE: Regex = Regex::new(r"([+-])\s*(\d+)").unwrap();
+    }
+
+    let groups = RE.captures(expression)?;
+    // a bare "iron" is more likely chat than a roll
+    let is_short_prefix = groups["prefix"].eq_ignore_ascii_case("iron");
+    if is_short_prefix && groups.name("progress").is_none() && groups.name("modifier").is_none() {
+        return None;
+    }
+    if let Some(number) = find_oversized_number(&groups) {
+        return Some(too_big_number_error(number));
+    }
+
+    let challenge1 = roll(10, 0);
+    let challenge2 = roll(10, 0);
+    // the stat and any adds, e.g. `+2+1`
+    let (normalized, score, score_str) = if groups.name("progress").is_some() {
+        let progress = parse(groups.name("progress_score"), 0).min(10) as i64;
+        (
+            format!("progress roll with {progress} progress"),
+            progress,
+            format!("Progress: **{progress}**"),
+        )
+    } else {
+        let mut modifier: i64 = 0;
+        for term in TERM_RE.captures_iter(groups.name("modifier").map_or("", |modifier| modifier.as_str())) {
+            let value = match term[2].parse::<i32>() {
+                Ok(value) => value as i64,
+                Err(_) => return Some(too_big_number_error(&term[2])),
+            };
+            if &term[1] == "-" {
+                modifier -= value;
+            } else {
+                modifier += value;
+            }
+        }
+        let action_die = roll(6, 0);
+        // the action score can't go above 10
+        let score = (action_die.value as i64 + modifier).min(10);
+        let action_die_str = format_roll(&action_die, false);
+        let modifier_str = format_modifier(modifier);
+        (
+            format!("action roll{modifier_str}"),
+            score,
+            format!("Action: {action_die_str}{modifier_str} → **{score}**"),
+        )
+    };
+
+    let challenge_str = format!("{} {}", format_roll(&challenge1, false), format_roll(&challenge2, false));
+    let outcome_str = format_outcome(score, challenge1.value, challenge2.value);
+
+    Some(format!("Rolling Ironsworn {normalized}:\n{score_str} vs Challenge: {challenge_str}\n{outcome_str}"))
+}
+
+// A strong hit beats both challenge dice, a weak hit one of them, and a miss neither.
+// The score has to strictly beat a challenge die: ties go to the challenge
+fn format_outcome(score: i64, challenge1: i32, challenge2: i32) -> String {
+    let beaten = [challenge1, challenge2]
+        .iter()
+        .filter(|challenge| score > **challenge as i64)
+        .count();
+    let outcome_str = match beaten {
+        2 => "**Strong hit!**",
+        1 => "**Weak hit**",
+        _ => "**Miss**",
+    };
+    if challenge1 == challenge2 {
+        format!("{outcome_str} — **Match!**")
+    } else {
+        outcome_str.to_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::eval_ironsworn::{eval_ironsworn, format_outcome};
+
+    #[test]
+    fn outcomes() {
+        assert_eq!(format_outcome(8, 3, 7), "**Strong hit!**");
+        assert_eq!(format_outcome(7, 3, 7), "**Weak hit**");
+        assert_eq!(format_outcome(2, 3, 7), "**Miss**");
+        assert_eq!(format_outcome(10, 4, 4), "**Strong hit!** — **Match!**");
+        assert_eq!(format_outcome(4, 4, 4), "**Miss** — **Match!**");
+    }
+    #[test]
+    fn bare_iron_is_not_a_roll() {
+        assert_eq!(eval_ironsworn("iron"), None);
+        assert!(eval_ironsworn("iron +2").unwrap().starts_with("Rolling Ironsworn action roll + 2:"));
+        assert!(eval_ironsworn("ironsworn").unwrap().starts_with("Rolling Ironsworn action roll:"));
+    }
+}
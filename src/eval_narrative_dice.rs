@@ -0,0 +1,249 @@
+use regex::Regex;
+
+use crate::util::{find_oversized_number, parse, roll, too_big_number_error};
+
+// Faces of the Genesys / Star Wars FFG narrative dice, one string per face.
+// Each character is a symbol on the face:
+// S = success, F = failure, A = advantage, T = threat, ! = triumph, D = despair,
+// L = light side, N = dark side. An empty string is a blank face
+const BOOST: [&str; 6] = ["", "", "S", "SA", "AA", "A"];
+const SETBACK: [&str; 6] = ["", "", "F", "F", "T", "T"];
+const ABILITY: [&str; 8] = ["", "S", "S", "SS", "A", "A", "SA", "AA"];
+const DIFFICULTY: [&str; 8] = ["", "F", "FF", "T", "T", "T", "TT", "FT"];
+const PROFICIENCY: [&str; 12] = ["", "S", "S", "SS", "SS", "A", "SA", "SA", "SA", "AA", "AA", "!"];
+const CHALLENGE: [&str; 12] = ["", "F", "F", "FF", "FF", "T", "T", "FT", "FT", "TT", "TT", "D"];
+const FORCE: [&str; 12] = ["N", "N", "N", "N", "N", "N", "NN", "L", "L", "LL", "LL", "LL"];
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum NarrativeDie {
+    Boost,
+    Setback,
+    Ability,
+    Difficulty,
+    Proficiency,
+    Challenge,
+    Force,
+}
+
+impl NarrativeDie {
+    fn from_name(name: &str) -> Option<NarrativeDie> {
+        match name.to_lowercase().as_str() {
+            "b" | "boost" | "blue" => Some(NarrativeDie::Boost),
+            "s" | "setback" | "black" => Some(NarrativeDie::Setback),
+            "a" | "ability" | "green" => Some(NarrativeDie::Ability),
+            "d" | "difficulty" | "purple" => Some(NarrativeDie::Difficulty),
+            "p" | "proficiency" | "yellow" => Some(NarrativeDie::Proficiency),
+            "c" | "challenge" | "red" => Some(NarrativeDie::Challenge),
+            "f" | "force" | "white" => Some(NarrativeDie::Force),
+            _ => None,
+        }
+    }
+
+    fn faces(self) -> &'static [&'static str] {
+        match self {
+            NarrativeDie::Boost => &BOOST,
+            NarrativeDie::Setback => &SETBACK,
+            NarrativeDie::Ability => &ABILITY,
+            NarrativeDie::Difficulty => &DIFFICULTY,
+            NarrativeDie::Proficiency => &PROFICIENCY,
+            NarrativeDie::Challenge => &CHALLENGE,
+            NarrativeDie::Force => &FORCE,
+        }
+    }
+
+    // the die's color, so each rolled face shows which die it came from
+    fn color(self) -> &'static str {
+        match self {
+            NarrativeDie::Boost => "🟦",
+            NarrativeDie::Setback => "⬛",
+            NarrativeDie::Ability => "🟩",
+            NarrativeDie::Difficulty => "🟪",
+            NarrativeDie::Proficiency => "🟨",
+            NarrativeDie::Challenge => "🟥",
+            NarrativeDie::Force => "⬜",
+        }
+    }
+}
+
+// Render a face's symbols, e.g. "SA" as "✦▲"
+fn format_face(face: &str) -> String {
+    if face.is_empty() {
+        return "–".to_owned();
+    }
+    face.chars()
+        .map(|symbol| match symbol {
+            'S' => '✦',
+            'F' => '✖',
+            'A' => '▲',
+            'T' => '▼',
+            '!' => '✪',
+            'D' => '☠',
+            'L' => '○',
+            _ => '●',
+        })
+        .collect()
+}
+
+// The net result of a roll once opposing symbols cancel out
+#[derive(Debug, Default, PartialEq, Eq)]
+struct NetResult {
+    successes: i64, // negative for net failures
+    advantages: i64, // negative for net threats
+    triumphs: i64,
+    despairs: i64,
+    light_side: i64,
+    dark_side: i64,
+}
+
+// Tally the symbols on the rolled faces.
+// Triumphs also count as a success and despairs also count as a failure
+fn net_result(faces: &[&str]) -> NetResult {
+    let mut result = NetResult::default();
+    for symbol in faces.iter().flat_map(|face| face.chars()) {
+        match symbol {
+            'S' => result.successes += 1,
+            'F' => result.successes -= 1,
+            'A' => result.advantages += 1,
+            'T' => result.advantages -= 1,
+            '!' => {
+                result.triumphs += 1;
+                result.successes += 1;
+            }
+            'D' => {
+                result.despairs += 1;
+                result.successes -= 1;
+            }
+            'L' => result.light_side += 1,
+            _ => result.dark_side += 1,
+        }
+    }
+    result
+}
+
+fn plural(count: i64, singular: &str, plural: &str) -> String {
+    if count == 1 {
+        format!("{count} {singular}")
+    } else {
+        format!("{count} {plural}")
+    }
+}
+
+// Evaluates a roll of Genesys / Star Wars FFG narrative dice such as `ffg 2a 1p 2d 1c 1b`
+// or `genesys 2 green 1 yellow 2 purple`, netting out successes/failures and advantages/threats
+pub fn eval_narrative_dice(expression: &str) -> Option<String> {
+    lazy_static! {
+        static ref RE: Regex = Regex::new(r"(?i)^(?:ffg|genesys)((?:\s*\d*\s*[a-z]+)+)\s*$").unwrap();
+        static ref DICE_RE: Regex = Regex::new(r"(?i)(?P<count>\d*)\s*(?P<die>[a-z]+)").unwrap();
+    }
+
+    let dice_str = RE.captures(expression)?.get(1)?.as_str().to_owned();
+    let mut dice: Vec<(i32, NarrativeDie)> = Vec::new();
+    for groups in DICE_RE.captures_iter(&dice_str) {
+        if let Some(number) = find_oversized_number(&groups) {
+            return Some(too_big_number_error(number));
+        }
+        let name = groups.name("die")?.as_str();
+        match NarrativeDie::from_name(name) {
+            Some(die) => dice.push((parse(groups.name("count"), 1), die)),
+            // a run of single-letter codes such as `aapdd` is one die per letter
+            None if groups.name("count")?.as_str().is_empty() => {
+                for code in name.chars() {
+                    let die = NarrativeDie::from_name(&code.to_string())?;
+                    match dice.last_mut() {
+                        Some((count, last_die)) if *last_die == die => *count += 1,
+                        _ => dice.push((1, die)),
+                    }
+                }
+            }
+            None => return None,
+        }
+    }
+    let num_dice: i64 = dice.iter().map(|(count, _)| *count as i64).sum();
+    if num_dice > 100 {
+        return Some("Can't roll more than 100 narrative dice at once.".to_owned());
+    }
+
+    // Roll each die by picking a face with the same RNG as the numbered dice
+    let rolled: Vec<(NarrativeDie, &str)> = dice
+        .iter()
+        .flat_map(|(count, die)| (0..*count).map(move |_| *die))
+        .map(|die| {
+            let faces = die.faces();
+            let face = faces[roll(faces.len() as i32, 0).value as usize - 1];
+            (die, face)
+        })
+        .collect();
+    let roll_str = rolled
+        .iter()
+        .map(|(die, face)| format!("{}{}", die.color(), format_face(face)))
+        .collect::<Vec<String>>()
+        .join(" ");
+
+    let faces: Vec<&str> = rolled.iter().map(|(_, face)| *face).collect();
+    let net = net_result(&faces);
+    let mut outcome = Vec::new();
+    // force dice only generate force points, so don't call a pure force roll a failure
+    let only_force = dice.iter().all(|(_, die)| matches!(die, NarrativeDie::Force));
+    if !only_force {
+        outcome.push(if net.successes > 0 {
+            format!("**Success** ({})", plural(net.successes, "success", "successes"))
+        } else if net.successes < 0 {
+            format!("**Failure** ({})", plural(-net.successes, "failure", "failures"))
+        } else {
+            "**Failure** (no net successes)".to_owned()
+        });
+    }
+    if net.advantages > 0 {
+        outcome.push(plural(net.advantages, "advantage", "advantages"));
+    } else if net.advantages < 0 {
+        outcome.push(plural(-net.advantages, "threat", "threats"));
+    }
+    if net.triumphs > 0 {
+        outcome.push(plural(net.triumphs, "triumph", "triumphs"));
+    }
+    if net.despairs > 0 {
+        outcome.push(plural(net.despairs, "despair", "despairs"));
+    }
+    if net.light_side > 0 || net.dark_side > 0 {
+        outcome.push(format!("{} light side, {} dark side", net.light_side, net.dark_side));
+    }
+    let outcome_str = outcome.join(", ");
+
+    let normalized = dice
+        .iter()
+        .map(|(count, die)| format!("{count}{}", die.color()))
+        .collect::<Vec<String>>()
+        .join(" ");
+    Some(format!("Rolling {normalized}:\n{roll_str}\n{outcome_str}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::eval_narrative_dice::{net_result, NetResult};
+
+    #[test]
+    fn net_successes_and_threats() {
+        assert_eq!(net_result(&["SS", "SA", "FT", "T", "T", ""]), NetResult {
+            successes: 2,
+            advantages: -2,
+            ..NetResult::default()
+        });
+    }
+    #[test]
+    fn triumph_and_despair_count_as_success_and_failure() {
+        assert_eq!(net_result(&["!", "D", "F"]), NetResult {
+            successes: -1,
+            triumphs: 1,
+            despairs: 1,
+            ..NetResult::default()
+        });
+    }
+    #[test]
+    fn force_points() {
+        assert_eq!(net_result(&["NN", "LL", "L"]), NetResult {
+            light_side: 3,
+            dark_side: 2,
+            ..NetResult::default()
+        });
+    }
+}
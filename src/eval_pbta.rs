@@ -0,0 +1,135 @@
+use std::collections::HashMap;
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::util::{find_oversized_number, format_modifier, format_roll, parse, roll, too_big_number_error, Roll};
+
+// Custom text for each outcome band of a Powered by the Apocalypse move,
+// set with `dicebot move <name>: <strong hit> | <weak hit> | <miss>`
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PbtaMove {
+    pub name: String,
+    pub strong_hit: String,
+    pub weak_hit: String,
+    pub miss: String,
+}
+
+// Evaluates a Powered by the Apocalypse roll such as `pbta +2` or `pbta -1 defy danger`:
+// rolls 2d6 plus the stat and spells out whether it's a strong hit, weak hit or miss.
+// In a channel with PbtA mode on, a plain `2d6+2` (or `2d6+2 defy danger`) is one too.
+// Anything after the stat has to be one of the channel's moves, keyed by lowercase move name
+pub fn eval_pbta(expression: &str, pbta_mode: bool, moves: &HashMap<String, PbtaMove>) -> Option<String> {
+    lazy_static! {
+        static ref RE: Regex = Regex::new(
+            r"(?i)^(?:(?P<prefix>pbta)\b\s*(?:2d6\s*)?|2d6\b\s*)(?:(?P<sign>[+-])\s*(?P<stat>\d+))?\s*(?P<move>.*?)\s*$"
+        ).unwrap();
+    }
+
+    let groups = RE.captures(expression)?;
+    let has_prefix = groups.name("prefix").is_some();
+    if !has_prefix && !pbta_mode {
+        return None;
+    }
+    let move_name = groups.name("move").map_or("", |name| name.as_str());
+    let custom_move = moves.get(&move_name.to_lowercase());
+    if custom_move.is_none() && !move_name.is_empty() {
+        // e.g. "pbta is fun" is just chat, and `2d6+2 adv` is a regular dice expression
+        return match (has_prefix, groups.name("stat")) {
+            (true, Some(_)) => Some(format!(
+                "There's no move called {move_name} in this channel. Save it with `dicebot move {move_name}: <strong hit> | <weak hit> | <miss>`."
+            )),
+            _ => None,
+        };
+    }
+    if let Some(number) = find_oversized_number(&groups) {
+        return Some(too_big_number_error(number));
+    }
+    let stat = match groups.name("sign").map(|sign| sign.as_str()) {
+        Some("-") => -parse(groups.name("stat"), 0),
+        _ => parse(groups.name("stat"), 0),
+    };
+
+    let rolls: Vec<Roll> = (1..=2).map(|_| roll(6, 0)).collect();
+    let roll_str = rolls
+        .iter()
+        .map(|roll| format_roll(roll, false))
+        .collect::<Vec<String>>()
+        .join(" + ");
+    let total = rolls.iter().fold(stat as i64, |acc, roll| acc + roll.value as i64);
+    let stat_str = format_modifier(stat as i64);
+    let band_str = format_band(total, custom_move);
+    let move_str = match custom_move {
+        Some(custom_move) => format!(" ({})", custom_move.name),
+        None => "".to_owned(),
+    };
+    Some(format!("Rolling 2d6{stat_str}{move_str}:\n{roll_str}{stat_str} → **{total}**\n{band_str}"))
+}
+
+// The outcome band for a total: 10+ is a strong hit, 7–9 a weak hit and 6 or less a miss,
+// followed by the move's text for that band, if it has any
+fn format_band(total: i64, custom_move: Option<&PbtaMove>) -> String {
+    let (band, custom_text) = if total >= 10 {
+        ("Strong hit!", custom_move.map(|custom_move| &custom_move.strong_hit))
+    } else if total >= 7 {
+        ("Weak hit", custom_move.map(|custom_move| &custom_move.weak_hit))
+    } else {
+        ("Miss", custom_move.map(|custom_move| &custom_move.miss))
+    };
+    match custom_text {
+        Some(text) if !text.is_empty() => format!("**{band}** {text}"),
+        _ => format!("**{band}**"),
+    }
+}
+
+// Parses a move definition such as `defy danger: You do it. | You stumble. | You fail.`
+pub fn parse_pbta_move(definition: &str) -> Option<PbtaMove> {
+    let (name, bands) = definition.split_once(':')?;
+    let mut bands = bands.split('|').map(|band| band.trim().to_owned());
+    let name = name.trim().to_owned();
+    if name.is_empty() {
+        return None;
+    }
+    Some(PbtaMove {
+        name,
+        strong_hit: bands.next().unwrap_or_default(),
+        weak_hit: bands.next().unwrap_or_default(),
+        miss: bands.next().unwrap_or_default(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::eval_pbta::{eval_pbta, format_band, parse_pbta_move};
+
+    #[test]
+    fn outcome_bands() {
+        assert_eq!(format_band(6, None), "**Miss**");
+        assert_eq!(format_band(7, None), "**Weak hit**");
+        assert_eq!(format_band(9, None), "**Weak hit**");
+        assert_eq!(format_band(10, None), "**Strong hit!**");
+        let defy_danger = parse_pbta_move("Defy Danger: You do it. | You stumble. | You fail.").unwrap();
+        assert_eq!(format_band(8, Some(&defy_danger)), "**Weak hit** You stumble.");
+    }
+    #[test]
+    fn rolls_need_pbta_or_pbta_mode() {
+        let moves = HashMap::new();
+        assert!(eval_pbta("pbta +12", false, &moves).unwrap().ends_with("**Strong hit!**"));
+        assert!(eval_pbta("2d6-12", true, &moves).unwrap().ends_with("**Miss**"));
+        assert_eq!(eval_pbta("2d6+2", false, &moves), None);
+        // other dice expressions are left alone in PbtA mode
+        assert_eq!(eval_pbta("2d6k1", true, &moves), None);
+        assert_eq!(eval_pbta("2d6+2 adv", true, &moves), None);
+    }
+    #[test]
+    fn free_text_is_not_a_move() {
+        let mut moves = HashMap::new();
+        assert_eq!(eval_pbta("pbta is fun", false, &moves), None);
+        assert!(eval_pbta("pbta +1 defy danger", false, &moves).unwrap().starts_with("There's no move called defy danger"));
+        let defy_danger = parse_pbta_move("Defy Danger: You do it. | You stumble. | You fail.").unwrap();
+        moves.insert("defy danger".to_owned(), defy_danger);
+        assert!(eval_pbta("pbta +12 defy danger", false, &moves).unwrap().ends_with("**Strong hit!** You do it."));
+    }
+}
@@ -0,0 +1,180 @@
+use std::collections::BTreeMap;
+
+use rand::seq::SliceRandom;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::eval_character::{ability_modifier, format_ability_modifier, point_buy_cost};
+
+// A point-buy budget and the cost of each score that can be bought, set per channel with `dicebot pointbuy`
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PointBuyRules {
+    pub budget: i32,
+    pub costs: BTreeMap<i32, i32>,
+}
+
+// The 5e rules: 27 points to spend on scores from 8 to 15
+impl Default for PointBuyRules {
+    fn default() -> Self {
+        PointBuyRules {
+            budget: 27,
+            costs: (8..=15).map(|score| (score, point_buy_cost(score))).collect(),
+        }
+    }
+}
+
+impl PointBuyRules {
+    fn min_score(&self) -> i32 {
+        *self.costs.keys().next().unwrap()
+    }
+
+    fn max_score(&self) -> i32 {
+        *self.costs.keys().next_back().unwrap()
+    }
+
+    // A random array that's legal under these rules and spends as much of the budget as it can
+    fn random_array(&self) -> Vec<i32> {
+        let mut scores = vec![self.min_score(); 6];
+        let mut spent: i32 = scores.iter().map(|score| self.costs[score]).sum();
+        loop {
+            // the abilities that could still go up by one without going over budget
+            let affordable: Vec<usize> = (0..6)
+                .filter(|i| match self.costs.get(&(scores[*i] + 1)) {
+                    Some(cost) => spent - self.costs[&scores[*i]] + cost <= self.budget,
+                    None => false,
+                })
+                .collect();
+            match affordable.choose(&mut rand::thread_rng()) {
+                Some(i) => {
+                    spent += self.costs[&(scores[*i] + 1)] - self.costs[&scores[*i]];
+                    scores[*i] += 1;
+                }
+                None => return scores,
+            }
+        }
+    }
+}
+
+// Parses a rules definition such as `32` (a new budget with the usual costs)
+// or `30 8:0 9:1 10:2 11:3 12:4 13:5 14:7 15:9 16:11` (a budget followed by the cost of each score)
+pub fn parse_point_buy_rules(definition: &str) -> Option<PointBuyRules> {
+    let mut terms = definition.split_whitespace();
+    let budget = terms.next()?.parse::<i32>().ok()?;
+    let costs = terms
+        .map(|term| {
+            let (score, cost) = term.split_once(':')?;
+            Some((score.parse::<i32>().ok()?, cost.parse::<i32>().ok()?))
+        })
+        .collect::<Option<BTreeMap<i32, i32>>>()?;
+    if budget < 0 {
+        return None;
+    }
+    if costs.is_empty() {
+        return Some(PointBuyRules {
+            budget,
+            ..PointBuyRules::default()
+        });
+    }
+    // every score between the lowest and highest needs a cost, and the lowest score has to be affordable
+    // (costs are kept small so that adding them up can't overflow)
+    let (min_score, max_score) = (*costs.keys().next()?, *costs.keys().next_back()?);
+    if costs.len() as i64 != max_score as i64 - min_score as i64 + 1
+        || costs.values().any(|cost| cost.abs() > 1000)
+        || costs[&min_score] * 6 > budget
+    {
+        return None;
+    }
+    Some(PointBuyRules { budget, costs })
+}
+
+// Evaluates a point-buy check such as `pointbuy 15 14 13 12 10 8`, reporting the cost and points left over,
+// or `pointbuy random` for a random legal array
+pub fn eval_point_buy(expression: &str, rules: &PointBuyRules) -> Option<String> {
+    lazy_static! {
+        static ref RE: Regex = Regex::new(
+            r"(?i)^point\s*buy\s*(?:(?P<random>rand(?:om)?)|(?P<scores>\d+(?:[\s,]+\d+)*))?\s*$"
+        ).unwrap();
+        static ref SCORE_RE: Regex = Regex::new(r"\d+").unwrap();
+    }
+
+    let groups = RE.captures(expression)?;
+    let (min_score, max_score) = (rules.min_score(), rules.max_score());
+    let scores: Vec<i32> = match groups.name("scores") {
+        Some(scores) => {
+            let mut parsed = Vec::new();
+            for score in SCORE_RE.find_iter(scores.as_str()) {
+                match score.as_str().parse::<i32>() {
+                    Ok(score) if (min_score..=max_score).contains(&score) => parsed.push(score),
+                    _ => {
+                        return Some(format!(
+                            "`{}` can't be bought. Scores must be between {min_score} and {max_score}.",
+                            score.as_str()
+                        ))
+                    }
+                }
+            }
+            parsed
+        }
+        None => rules.random_array(),
+    };
+    if scores.len() != 6 {
+        return Some(format!("Point buy needs six scores, but got {}.", scores.len()));
+    }
+
+    let costs: Vec<i32> = scores.iter().map(|score| rules.costs[score]).collect();
+    let spent: i32 = costs.iter().sum();
+    let remaining = rules.budget - spent;
+    let scores_str = scores
+        .iter()
+        .map(|score| format!("**{score}** ({})", format_ability_modifier(ability_modifier(*score))))
+        .collect::<Vec<String>>()
+        .join(" · ");
+    let costs_str = costs
+        .iter()
+        .map(|cost| cost.to_string())
+        .collect::<Vec<String>>()
+        .join(" + ");
+    let outcome_str = match remaining {
+        0 => "✓ Legal, with every point spent".to_owned(),
+        1 => "✓ Legal, with 1 point left to spend".to_owned(),
+        remaining if remaining > 0 => format!("✓ Legal, with {remaining} points left to spend"),
+        -1 => "✗ Over budget by 1 point".to_owned(),
+        remaining => format!("✗ Over budget by {} points", -remaining),
+    };
+
+    let action = if groups.name("scores").is_some() {
+        "Checking"
+    } else {
+        "Generating"
+    };
+    let budget = rules.budget;
+    Some(format!(
+        "{action} a {budget}-point buy:\n{scores_str}\nCost: {costs_str} = **{spent}**\n{outcome_str}"
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::eval_point_buy::{parse_point_buy_rules, PointBuyRules};
+
+    #[test]
+    fn random_arrays_are_legal() {
+        let rules = PointBuyRules::default();
+        for _ in 0..100 {
+            let scores = rules.random_array();
+            let spent: i32 = scores.iter().map(|score| rules.costs[score]).sum();
+            assert!(spent <= rules.budget);
+            // with the 5e costs, 27 points can always be spent exactly
+            assert_eq!(spent, 27);
+        }
+    }
+    #[test]
+    fn custom_rules() {
+        assert_eq!(parse_point_buy_rules("32").map(|rules| rules.budget), Some(32));
+        let rules = parse_point_buy_rules("20 7:0 8:1 9:2").unwrap();
+        assert_eq!(rules.costs.len(), 3);
+        // the table can't have gaps
+        assert_eq!(parse_point_buy_rules("20 8:0 10:2"), None);
+        assert_eq!(parse_point_buy_rules("lots"), None);
+    }
+}
@@ -0,0 +1,25 @@
+use crate::eval_attack::eval_attack;
+use crate::eval_blades::eval_blades;
+use crate::eval_daggerheart::eval_daggerheart;
+use crate::eval_dice_expression::eval_dice_expression;
+use crate::eval_ironsworn::eval_ironsworn;
+use crate::eval_narrative_dice::eval_narrative_dice;
+use crate::eval_pbta::eval_pbta;
+use crate::eval_savage_worlds::eval_savage_worlds;
+use crate::eval_year_zero::eval_year_zero;
+use crate::settings::ChannelSettings;
+
+// Evaluates a roll in any of the formats the bot understands, from plain dice expressions to system-specific rolls.
+// Each format has its own prefix, so at most one of them matches (PbtA mode aside)
+pub fn eval_roll(expression: &str, user_id: u64, settings: &ChannelSettings) -> Option<String> {
+    // PbtA mode claims plain `2d6+N` rolls, so it goes before regular dice expressions
+    eval_pbta(expression, settings.pbta_mode, &settings.pbta_moves)
+        .or_else(|| eval_dice_expression(expression, settings.roll_under_system))
+        .or_else(|| eval_attack(expression))
+        .or_else(|| eval_blades(expression))
+        .or_else(|| eval_narrative_dice(expression))
+        .or_else(|| eval_year_zero(expression, user_id))
+        .or_else(|| eval_ironsworn(expression))
+        .or_else(|| eval_savage_worlds(expression))
+        .or_else(|| eval_daggerheart(expression))
+}
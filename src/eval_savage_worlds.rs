@@ -0,0 +1,106 @@
+use regex::Regex;
+
+use crate::util::{find_oversized_number, format_modifier, format_roll, keep_best, parse, roll_exploding, too_big_number_error, MarkCondition};
+
+// Evaluates a Savage Worlds trait roll such as `sw d8`, `sw d8+1` or `sw d6-2 vs 6`:
+// rolls the trait die and a d6 wild die, both acing, and keeps the higher.
+// Every 4 points over the target number is a raise, and 1s on both dice are a critical failure.
+// `extra` (or `nowild`) rolls the trait die alone, as for Extras
+pub fn eval_savage_worlds(expression: &str) -> Option<String> {
+    lazy_static! {
+        static ref RE: Regex = Regex::new(
+            r"(?i)^sw\s*d(?P<dice_size>\d+)\s*(?:(?P<sign>[+-])\s*(?P<modifier>\d+))?\s*(?:(?:vs|tn)\s*(?P<target>\d+))?\s*(?P<extra>extra|nowild|nw)?\s*$"
+        ).unwrap();
+    }
+
+    let groups = RE.captures(expression)?;
+    if let Some(number) = find_oversized_number(&groups) {
+        return Some(too_big_number_error(number));
+    }
+    let dice_size = parse(groups.name("dice_size"), 6);
+    if dice_size < 2 {
+        return Some("Trait dice need at least two sides.".to_owned());
+    }
+    let modifier = match groups.name("sign").map(|sign| sign.as_str()) {
+        Some("-") => -parse(groups.name("modifier"), 0),
+        _ => parse(groups.name("modifier"), 0),
+    };
+    let target = parse(groups.name("target"), 4) as i64;
+    let wild_die = groups.name("extra").is_none();
+
+    // Like advantage: roll the trait die and the wild die, keeping whichever is higher
+    let (trait_total, trait_face, trait_str) = roll_acing(dice_size, "trait");
+    let mut rolls = vec![(trait_total, trait_str)];
+    let mut wild_face = None;
+    if wild_die {
+        let (wild_total, face, wild_str) = roll_acing(6, "wild");
+        rolls.push((wild_total, wild_str));
+        wild_face = Some(face);
+    }
+    let (kept_total, roll_str) = keep_best(&rolls, MarkCondition::Highest);
+    let total = kept_total + modifier as i64;
+    let modifier_str = format_modifier(modifier as i64);
+    let outcome_str = format_outcome(total, target, trait_face, wild_face);
+
+    let wild_die_str = if wild_die { " with a d6 wild die" } else { "" };
+    Some(format!(
+        "Rolling d{dice_size}{modifier_str}{wild_die_str} vs {target}:\n{roll_str}{modifier_str} → **{total}**\n{outcome_str}"
+    ))
+}
+
+// Rolls an acing die, returning its total, its first face and how to display it
+fn roll_acing(dice_size: i32, label: &str) -> (i64, i32, String) {
+    let (total, rolls) = roll_exploding(dice_size);
+    let chain_str = rolls
+        .iter()
+        .map(|roll| format_roll(roll, false))
+        .collect::<Vec<String>>()
+        .join(" + ");
+    (total, rolls[0].value, format!("{chain_str} ({label})"))
+}
+
+// Every 4 points over the target is a raise, but 1s on both the trait die and the wild die are a critical failure.
+// Extras have no wild die, so they can't critically fail
+fn format_outcome(total: i64, target: i64, trait_face: i32, wild_face: Option<i32>) -> String {
+    if trait_face == 1 && wild_face == Some(1) {
+        "**Critical failure!**".to_owned()
+    } else if total >= target {
+        let raises = (total - target) / 4;
+        match raises {
+            0 => "**Success!**".to_owned(),
+            1 => "**Success with a raise!**".to_owned(),
+            raises => format!("**Success with {raises} raises!**"),
+        }
+    } else {
+        "**Failure**".to_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::eval_savage_worlds::{eval_savage_worlds, format_outcome};
+
+    #[test]
+    fn raises_every_4_over_the_target() {
+        assert_eq!(format_outcome(3, 4, 3, Some(2)), "**Failure**");
+        assert_eq!(format_outcome(4, 4, 4, Some(2)), "**Success!**");
+        assert_eq!(format_outcome(7, 4, 7, Some(2)), "**Success!**");
+        assert_eq!(format_outcome(8, 4, 8, Some(2)), "**Success with a raise!**");
+        assert_eq!(format_outcome(16, 4, 6, Some(2)), "**Success with 3 raises!**");
+    }
+    #[test]
+    fn double_ones_are_a_critical_failure() {
+        // even a big enough modifier doesn't save it
+        assert_eq!(format_outcome(6, 4, 1, Some(1)), "**Critical failure!**");
+        assert_eq!(format_outcome(1, 4, 1, Some(3)), "**Failure**");
+    }
+    #[test]
+    fn extras_have_no_wild_die() {
+        assert_eq!(format_outcome(1, 4, 1, None), "**Failure**");
+        assert_eq!(format_outcome(5, 4, 1, None), "**Success!**");
+        let result = eval_savage_worlds("sw d8+1 vs 6 extra").unwrap();
+        assert!(result.starts_with("Rolling d8 + 1 vs 6:\n"));
+        assert!(!result.contains("(wild)"));
+        assert!(eval_savage_worlds("sw d8").unwrap().contains("(wild)"));
+    }
+}
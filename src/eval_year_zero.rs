@@ -0,0 +1,145 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use regex::Regex;
+
+use crate::util::{find_oversized_number, format_roll, parse, roll, too_big_number_error, Roll};
+
+// The three dice pools of a Year Zero Engine roll
+#[derive(Clone)]
+struct YearZeroRoll {
+    base: Vec<Roll>,
+    skill: Vec<Roll>,
+    gear: Vec<Roll>,
+    pushed: bool,
+}
+
+lazy_static! {
+    // Each user's most recent roll, so that it can be pushed with `yze push`
+    static ref LAST_ROLLS: Mutex<HashMap<u64, YearZeroRoll>> = Mutex::new(HashMap::new());
+}
+
+fn count_faces(pool: &[Roll], face: i32) -> usize {
+    pool.iter().filter(|roll| roll.value == face).count()
+}
+
+fn format_pool(pool: &[Roll]) -> String {
+    if pool.is_empty() {
+        return "–".to_owned();
+    }
+    pool.iter()
+        .map(|roll| format_roll(roll, false))
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+// Push a roll: reroll every die that isn't a 6, except that 1s on base and gear dice stay put
+fn push(previous: &YearZeroRoll) -> YearZeroRoll {
+    let reroll_pool = |pool: &[Roll], keep_ones: bool| -> Vec<Roll> {
+        pool.iter()
+            .map(|die| {
+                if die.value == 6 || (keep_ones && die.value == 1) {
+                    die.clone()
+                } else {
+                    Roll {
+                        old_value: Some(die.value),
+                        ..roll(6, 0)
+                    }
+                }
+            })
+            .collect()
+    };
+    YearZeroRoll {
+        base: reroll_pool(&previous.base, true),
+        skill: reroll_pool(&previous.skill, false),
+        gear: reroll_pool(&previous.gear, true),
+        pushed: true,
+    }
+}
+
+// Evaluates a Year Zero Engine roll such as `yze 4 2 1` (base, skill and gear dice)
+// or `yze push` to push the user's previous roll.
+// Every 6 is a success; 1s on base and gear dice are banes, which only matter once the roll is pushed
+pub fn eval_year_zero(expression: &str, user_id: u64) -> Option<String> {
+    lazy_static! {
+        static ref RE: Regex = Regex::new(
+            r"(?i)^(?:yze|yz|year\s*zero)\s+(?:(?P<push>push)|(?P<base>\d+)(?:\s+(?P<skill>\d+))?(?:\s+(?P<gear>\d+))?)\s*$"
+        ).unwrap();
+    }
+
+    let groups = RE.captures(expression)?;
+    let mut last_rolls = LAST_ROLLS.lock().unwrap();
+    let current = if groups.name("push").is_some() {
+        match last_rolls.get(&user_id) {
+            Some(previous) if previous.pushed => return Some("That roll has already been pushed.".to_owned()),
+            Some(previous) => push(previous),
+            None => return Some("There's no roll to push. Roll with `yze <base> <skill> <gear>` first.".to_owned()),
+        }
+    } else {
+        if let Some(number) = find_oversized_number(&groups) {
+            return Some(too_big_number_error(number));
+        }
+        let base = parse(groups.name("base"), 0);
+        let skill = parse(groups.name("skill"), 0);
+        let gear = parse(groups.name("gear"), 0);
+        if base as i64 + skill as i64 + gear as i64 > 100 {
+            return Some("Can't roll more than 100 dice at once.".to_owned());
+        }
+        let roll_pool = |size: i32| (0..size).map(|_| roll(6, 0)).collect::<Vec<Roll>>();
+        YearZeroRoll {
+            base: roll_pool(base),
+            skill: roll_pool(skill),
+            gear: roll_pool(gear),
+            pushed: false,
+        }
+    };
+    last_rolls.insert(user_id, current.clone());
+
+    let successes = count_faces(&current.base, 6) + count_faces(&current.skill, 6) + count_faces(&current.gear, 6);
+    let base_banes = count_faces(&current.base, 1);
+    let gear_banes = count_faces(&current.gear, 1);
+    let successes_str = match successes {
+        0 => "**No successes**".to_owned(),
+        1 => "**1 success**".to_owned(),
+        successes => format!("**{successes} successes**"),
+    };
+    let banes_str = if current.pushed && (base_banes > 0 || gear_banes > 0) {
+        format!("\nBanes: {base_banes} on base dice (damage or stress), {gear_banes} on gear dice (gear bonus lost)")
+    } else if !current.pushed {
+        "\n(Push with `yze push`)".to_owned()
+    } else {
+        "".to_owned()
+    };
+
+    let action = if current.pushed { "Pushing" } else { "Rolling" };
+    let (base, skill, gear) = (current.base.len(), current.skill.len(), current.gear.len());
+    Some(format!(
+        "{action} {base} base, {skill} skill and {gear} gear dice:\nBase: {} · Skill: {} · Gear: {}\n{successes_str}{banes_str}",
+        format_pool(&current.base),
+        format_pool(&current.skill),
+        format_pool(&current.gear),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::eval_year_zero::{push, YearZeroRoll};
+    use crate::util::Roll;
+
+    #[test]
+    fn push_keeps_sixes_and_banes() {
+        let previous = YearZeroRoll {
+            base: vec![Roll::new(6), Roll::new(1), Roll::new(3)],
+            skill: vec![Roll::new(1), Roll::new(6)],
+            gear: vec![Roll::new(1)],
+            pushed: false,
+        };
+        let pushed = push(&previous);
+        assert!(pushed.pushed);
+        assert!(pushed.base[0] == Roll::new(6) && pushed.base[1] == Roll::new(1));
+        assert_eq!(pushed.base[2].old_value, Some(3));
+        // 1s on skill dice aren't banes, so they get rerolled
+        assert_eq!(pushed.skill[0].old_value, Some(1));
+        assert!(pushed.skill[1] == Roll::new(6));
+        assert!(pushed.gear[0] == Roll::new(1));
+    }
+}
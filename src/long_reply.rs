@@ -0,0 +1,81 @@
+// Discord rejects messages longer than this many characters
+pub const MAX_MESSAGE_LENGTH: usize = 2000;
+
+// Name of the text file that replies too long for a message are attached as
+pub const ATTACHMENT_FILENAME: &str = "roll.txt";
+
+const ATTACHED_NOTE: &str = "*Too long for one message, so the full roll is attached.*";
+
+// A reply cut down to fit in a single message
+pub struct FittedReply {
+    pub content: String,
+    // the full reply, if it didn't fit, for attaching as a text file
+    pub attachment: Option<String>,
+}
+
+pub fn fit_reply(reply: String) -> FittedReply {
+    if reply.chars().count() <= MAX_MESSAGE_LENGTH {
+        return FittedReply {
+            content: reply,
+            attachment: None,
+        };
+    }
+    FittedReply {
+        content: summarize(&reply),
+        // a text file doesn't render markdown, so drop the bold
+        attachment: Some(reply.replace("**", "")),
+    }
+}
+
+// Shorten each line to its result (what follows the last →), keeping as many lines as fit
+fn summarize(reply: &str) -> String {
+    let budget = MAX_MESSAGE_LENGTH - ATTACHED_NOTE.chars().count() - 2;
+    let mut summary = String::new();
+    let mut length = 0;
+    for line in reply.lines() {
+        let line = match line.rsplit_once(" → ") {
+            Some((_, result)) => format!("… → {result}"),
+            None => line.to_owned(),
+        };
+        let line_length = line.chars().count();
+        if length + line_length + 1 > budget {
+            let remaining = budget.saturating_sub(length + 1);
+            summary.extend(line.chars().take(remaining));
+            summary.push_str("…\n");
+            break;
+        }
+        summary.push_str(&line);
+        summary.push('\n');
+        length += line_length + 1;
+    }
+    summary.push_str(ATTACHED_NOTE);
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::eval_dice_expression::eval_dice_expression;
+    use crate::long_reply::{fit_reply, MAX_MESSAGE_LENGTH};
+    use crate::roll_under::RollUnderSystem;
+
+    #[test]
+    fn short_replies_are_unchanged() {
+        let fitted = fit_reply("Rolling 1d20:\n⑫ → **12**".to_owned());
+        assert_eq!(fitted.content, "Rolling 1d20:\n⑫ → **12**");
+        assert!(fitted.attachment.is_none());
+    }
+    #[test]
+    fn long_replies_keep_results_and_total() {
+        let reply = eval_dice_expression("50d100*20", RollUnderSystem::Generic).unwrap();
+        let fitted = fit_reply(reply.clone());
+        assert!(fitted.content.chars().count() <= MAX_MESSAGE_LENGTH);
+        assert!(fitted.content.starts_with("Rolling 50d100, repeating 20 times:\n… → **"));
+        assert!(fitted.content.contains(reply.lines().last().unwrap()));
+        assert_eq!(fitted.attachment, Some(reply.replace("**", "")));
+    }
+    #[test]
+    fn long_lines_are_cut() {
+        let fitted = fit_reply("x".repeat(5000));
+        assert!(fitted.content.chars().count() <= MAX_MESSAGE_LENGTH);
+    }
+}
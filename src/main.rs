@@ -2,24 +2,66 @@
 extern crate lazy_static;
 
 mod util;
+mod embed_renderer;
 mod eval_dice_expression;
+mod eval_attack;
+mod eval_blades;
+mod eval_character;
+mod eval_daggerheart;
+mod eval_ironsworn;
+mod eval_narrative_dice;
+mod eval_pbta;
+mod eval_point_buy;
+mod eval_roll;
+mod eval_savage_worlds;
+mod eval_year_zero;
+mod long_reply;
+mod roll_buttons;
+mod roll_under;
+mod secret_roll;
+mod settings;
+mod slash_commands;
+mod stat_assignment;
 
 use std::{
     env,
     fs::{read_to_string, File}, path::Path,
 };
 
-use indoc::indoc;
 use regex::{Regex};
 use serenity::{
     async_trait,
     client::{Context, EventHandler},
-    model::channel::Message,
+    http::Http,
+    model::{
+        channel::Message,
+        gateway::Ready,
+        interactions::{
+            application_command::ApplicationCommand, Interaction, InteractionApplicationCommandCallbackDataFlags,
+            InteractionResponseType,
+        },
+    },
     Client,
 };
 
 
-use crate::eval_dice_expression::{eval_dice_expression};
+use crate::embed_renderer::Renderer;
+use crate::eval_character::eval_character;
+use crate::eval_pbta::parse_pbta_move;
+use crate::eval_point_buy::{eval_point_buy, parse_point_buy_rules, PointBuyRules};
+use crate::eval_roll::eval_roll;
+use crate::long_reply::{fit_reply, ATTACHMENT_FILENAME};
+use crate::roll_buttons::{eval_roll_with_buttons, handle_roll_button, is_roll_button, store_roll, RollButtonReply};
+use crate::roll_under::RollUnderSystem;
+use crate::secret_roll::{
+    can_list_members, deliver_secret_roll, member_can_manage_gms, parse_gm_mentions, parse_secret_roll,
+    respond_with_secret_roll, secret_roll_announcement, MEMBERS_INTENT_NOTE,
+};
+use crate::settings::{channel_settings, guild_settings, update_channel_settings, update_guild_settings};
+use crate::slash_commands::{autocomplete_macros, create_slash_commands, eval_slash_command, parse_macro, SlashReply};
+use crate::stat_assignment::{
+    handle_assignment_component, start_assignment, AssignmentReply, StatAssignment, ASSIGN_PREFIX, RACE_ID,
+};
 
 #[tokio::main]
 async fn main() {
@@ -39,7 +81,16 @@ async fn main() {
     match bot_token {
         Some(token) => {
             println!("Starting server.");
+            // Interactions (e.g. the stat assignment buttons) need the bot's application id
+            let application_id = match Http::new_with_token(&token).get_current_application_info().await {
+                Ok(info) => info.id.0,
+                Err(why) => {
+                    eprintln!("ERROR: Failed to fetch the bot's application info: {:?}", why);
+                    return;
+                }
+            };
             let mut client = Client::builder(&token)
+                .application_id(application_id)
                 .event_handler(Handler)
                 .await
                 .expect("Error creating client");
@@ -68,28 +119,259 @@ impl EventHandler for Handler {
         if msg.author.bot {
             return;
         }
-        if let Some(result) = eval_dice_expression(&msg.content) {
-            if let Err(why) = msg.reply_ping(&ctx.http, result).await {
+        let settings = channel_settings(msg.channel_id.0);
+        let guild = guild_settings(msg.guild_id.map(|guild_id| guild_id.0));
+        let reply = match eval_roll_with_buttons(&msg.content, &settings, guild.renderer) {
+            // Dice expressions get buttons for rolling them again
+            Some((result, Some(stored))) => {
+                let fitted = fit_reply(result.content);
+                let sent = msg
+                    .channel_id
+                    .send_message(&ctx.http, |message| {
+                        message
+                            .content(&fitted.content)
+                            .set_embeds(result.embed.into_iter().collect())
+                            .add_files(fitted.attachment.as_ref().map(|text| (text.as_bytes(), ATTACHMENT_FILENAME)))
+                            .reference_message(&msg)
+                            .set_components(stored.components())
+                    })
+                    .await;
+                match sent {
+                    Ok(sent) => store_roll(sent.id.0, stored),
+                    Err(why) => eprintln!("Error sending message: {:?}", why),
+                }
+                None
+            }
+            Some((result, None)) => Some(result.content),
+            None => eval_roll(&msg.content, msg.author.id.0, &settings),
+        };
+        if let Some(reply) = reply {
+            let fitted = fit_reply(reply);
+            let sent = match &fitted.attachment {
+                None => msg.reply_ping(&ctx.http, &fitted.content).await,
+                // too long for a message, so send a summary with the full reply attached
+                Some(attachment) => {
+                    msg.channel_id
+                        .send_message(&ctx.http, |message| {
+                            message
+                                .content(&fitted.content)
+                                .add_file((attachment.as_bytes(), ATTACHMENT_FILENAME))
+                                .reference_message(&msg)
+                        })
+                        .await
+                }
+            };
+            if let Err(why) = sent {
+                eprintln!("Error sending message: {:?}", why);
+            }
+        }
+
+        match eval_character(&msg.content) {
+            // Send the scores with buttons for assigning them to abilities
+            Some(Ok((result, scores))) => {
+                let assignment = StatAssignment::new(&scores, msg.author.id.0, result);
+                let sent = msg
+                    .channel_id
+                    .send_message(&ctx.http, |message| {
+                        message.content(assignment.content()).set_components(assignment.components())
+                    })
+                    .await;
+                match sent {
+                    Ok(sent) => start_assignment(sent.id.0, assignment),
+                    Err(why) => eprintln!("Error sending message: {:?}", why),
+                }
+            }
+            Some(Err(error)) => {
+                if let Err(why) = msg.channel_id.say(&ctx.http, error).await {
+                    eprintln!("Error sending message: {:?}", why);
+                }
+            }
+            None => {}
+        }
+
+        if let Some(result) = eval_point_buy(&msg.content, &settings.point_buy) {
+            if let Err(why) = msg.channel_id.say(&ctx.http, result).await {
+                eprintln!("Error sending message: {:?}", why);
+            }
+        }
+
+        if let Some(captures) = SYSTEM_RE.captures(&msg.content) {
+            let reply = match RollUnderSystem::from_keyword(&captures[1]) {
+                Some(system) => {
+                    update_channel_settings(msg.channel_id.0, |settings| settings.roll_under_system = system);
+                    let name = system.name();
+                    format!("Roll-under rolls in this channel now use {name} rules.")
+                }
+                None => "Unknown system. Try `coc`, `gurps` or `generic`.".to_owned(),
+            };
+            if let Err(why) = msg.channel_id.say(&ctx.http, reply).await {
                 eprintln!("Error sending message: {:?}", why);
             }
         }
-        
 
-        if RANDCHAR_RE.is_match(&msg.content) {
-            let reply = indoc! {"
-                Example dice expression: `4d6r1k3*6`
-                `4d6`: Roll four 6-sided dice
-                `r1`: Reroll dice that land on <= 1
-                `k3`/`kh3`: Keep highest three rolls
-                (Alternatively: `d1`/`dl1`: Drop lowest roll, i.e. `4d6r1d1*6`)
-                `*6`: Repeat 6 times
-            "};
+        if let Some(captures) = PBTA_MODE_RE.captures(&msg.content) {
+            let pbta_mode = captures[1].eq_ignore_ascii_case("on");
+            update_channel_settings(msg.channel_id.0, |settings| settings.pbta_mode = pbta_mode);
+            let reply = if pbta_mode {
+                "`2d6+N` rolls in this channel now show Powered by the Apocalypse outcomes."
+            } else {
+                "`2d6+N` rolls in this channel are now regular dice rolls. Use `pbta +N` for outcomes."
+            };
             if let Err(why) = msg.channel_id.say(&ctx.http, reply).await {
                 eprintln!("Error sending message: {:?}", why);
             }
         }
 
-        if *(&msg.content.starts_with("dicebot version")) {
+        if let Some(expression) = parse_secret_roll(&msg.content) {
+            if let Some(result) = eval_roll(expression, msg.author.id.0, &settings) {
+                let roller = (msg.author.id.0, msg.author.name.as_str());
+                let delivered = deliver_secret_roll(&ctx, msg.guild_id, msg.channel_id, roller, &guild, &result).await;
+                let reply = secret_roll_announcement(&msg.author.name, &delivered);
+                if let Err(why) = msg.channel_id.say(&ctx.http, reply).await {
+                    eprintln!("Error sending message: {:?}", why);
+                }
+            }
+        }
+
+        if let Some(captures) = GM_RE.captures(&msg.content) {
+            let (user_ids, role_ids) = parse_gm_mentions(&captures[2]);
+            let command = captures[1].to_lowercase();
+            // anyone can list the GMs, but only the server's managers can change them
+            let can_manage = match msg.guild_id {
+                Some(guild_id) if command != "list" => {
+                    let member_roles = msg.member.as_ref().map(|member| member.roles.as_slice()).unwrap_or_default();
+                    member_can_manage_gms(&ctx, guild_id, msg.author.id, member_roles).await
+                }
+                _ => true,
+            };
+            let reply = match (msg.guild_id, command.as_str()) {
+                (None, _) => "GMs can only be set in a server.".to_owned(),
+                (Some(_), _) if !can_manage => {
+                    "Only the server's owner and members with the Manage Server permission can change the GMs.".to_owned()
+                }
+                (Some(_), "add" | "remove") if user_ids.is_empty() && role_ids.is_empty() => {
+                    "Usage: `dicebot gm add|remove <@user or @role> ...` or `dicebot gm list`".to_owned()
+                }
+                (Some(guild_id), "add") => {
+                    update_guild_settings(guild_id.0, |settings| {
+                        settings.gm_users.extend(&user_ids);
+                        settings.gm_roles.extend(&role_ids);
+                    });
+                    // catch a missing Server Members intent now, rather than on the first secret roll
+                    if !role_ids.is_empty() && !can_list_members(&ctx, guild_id).await {
+                        format!("Secret rolls in this server will now be sent to them too.\n{MEMBERS_INTENT_NOTE}")
+                    } else {
+                        "Secret rolls in this server will now be sent to them too.".to_owned()
+                    }
+                }
+                (Some(guild_id), "remove") => {
+                    update_guild_settings(guild_id.0, |settings| {
+                        settings.gm_users.retain(|id| !user_ids.contains(id));
+                        settings.gm_roles.retain(|id| !role_ids.contains(id));
+                    });
+                    "Secret rolls in this server will no longer be sent to them.".to_owned()
+                }
+                (Some(_), _) => {
+                    let gms: Vec<String> = guild.gm_users.iter().map(|id| format!("<@{id}>"))
+                        .chain(guild.gm_roles.iter().map(|id| format!("<@&{id}>")))
+                        .collect();
+                    if gms.is_empty() {
+                        "This server has no GMs. Add them with `dicebot gm add <@user or @role>`.".to_owned()
+                    } else {
+                        format!("Secret rolls in this server are sent to {}.", gms.join(", "))
+                    }
+                }
+            };
+            // list the GMs without pinging them
+            let sent = msg
+                .channel_id
+                .send_message(&ctx.http, |message| {
+                    message.content(reply).allowed_mentions(|mentions| mentions.empty_parse())
+                })
+                .await;
+            if let Err(why) = sent {
+                eprintln!("Error sending message: {:?}", why);
+            }
+        }
+
+        if let Some(captures) = OUTPUT_RE.captures(&msg.content) {
+            let reply = match (msg.guild_id, Renderer::from_keyword(&captures[1])) {
+                (Some(guild_id), Some(renderer)) => {
+                    update_guild_settings(guild_id.0, |settings| settings.renderer = renderer);
+                    let name = renderer.name();
+                    format!("Dice rolls in this server are now shown as {name}.")
+                }
+                (None, _) => "The output style can only be changed in a server.".to_owned(),
+                (_, None) => "Unknown output style. Try `embed` or `text`.".to_owned(),
+            };
+            if let Err(why) = msg.channel_id.say(&ctx.http, reply).await {
+                eprintln!("Error sending message: {:?}", why);
+            }
+        }
+
+        if let Some(captures) = MOVE_RE.captures(&msg.content) {
+            let reply = match parse_pbta_move(&captures[1]) {
+                Some(pbta_move) => {
+                    let name = pbta_move.name.clone();
+                    update_channel_settings(msg.channel_id.0, |settings| {
+                        settings.pbta_moves.insert(name.to_lowercase(), pbta_move);
+                    });
+                    format!("Saved the move {name} for `pbta` rolls in this channel.")
+                }
+                None => "Usage: `dicebot move <name>: <strong hit> | <weak hit> | <miss>`".to_owned(),
+            };
+            if let Err(why) = msg.channel_id.say(&ctx.http, reply).await {
+                eprintln!("Error sending message: {:?}", why);
+            }
+        }
+
+        if let Some(captures) = POINT_BUY_RE.captures(&msg.content) {
+            let definition = captures[1].trim();
+            let rules = if definition.eq_ignore_ascii_case("default") {
+                Some(PointBuyRules::default())
+            } else {
+                parse_point_buy_rules(definition)
+            };
+            let reply = match rules {
+                Some(rules) => {
+                    let budget = rules.budget;
+                    update_channel_settings(msg.channel_id.0, |settings| settings.point_buy = rules);
+                    format!("Point buy in this channel now has a budget of {budget} points.")
+                }
+                None => "Usage: `dicebot pointbuy <budget> [<score>:<cost> ...]` or `dicebot pointbuy default`".to_owned(),
+            };
+            if let Err(why) = msg.channel_id.say(&ctx.http, reply).await {
+                eprintln!("Error sending message: {:?}", why);
+            }
+        }
+
+        if let Some(captures) = MACRO_RE.captures(&msg.content) {
+            let reply = match parse_macro(&captures[1]) {
+                Some((name, expression)) if expression.is_empty() => {
+                    let mut removed = false;
+                    update_channel_settings(msg.channel_id.0, |settings| {
+                        removed = settings.macros.remove(&name).is_some();
+                    });
+                    if removed {
+                        format!("Deleted the macro {name}.")
+                    } else {
+                        format!("There's no macro called {name} in this channel.")
+                    }
+                }
+                Some((name, expression)) => {
+                    update_channel_settings(msg.channel_id.0, |settings| {
+                        settings.macros.insert(name.clone(), expression);
+                    });
+                    format!("Saved the macro {name}. Roll it with `/roll {name}`.")
+                }
+                None => "Usage: `dicebot macro <name>: <expression>`, or `dicebot macro <name>:` to delete it".to_owned(),
+            };
+            if let Err(why) = msg.channel_id.say(&ctx.http, reply).await {
+                eprintln!("Error sending message: {:?}", why);
+            }
+        }
+
+        if msg.content.starts_with("dicebot version") {
             let version = env!("CARGO_PKG_VERSION");
             let reply = format!("DiceBot v{version}");
             if let Err(why) = msg.channel_id.say(&ctx.http, reply).await {
@@ -97,7 +379,168 @@ impl EventHandler for Handler {
             }
         }
     }
+
+    async fn ready(&self, ctx: Context, _ready: Ready) {
+        if let Err(why) = ApplicationCommand::set_global_application_commands(&ctx.http, create_slash_commands).await {
+            eprintln!("Error registering slash commands: {:?}", why);
+        }
+    }
+
+    async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
+        if let Interaction::ApplicationCommand(command) = &interaction {
+            let settings = channel_settings(command.channel_id.0);
+            let guild = guild_settings(command.guild_id.map(|guild_id| guild_id.0));
+            let reply = eval_slash_command(&command.data, command.user.id.0, &settings, guild.renderer);
+            let (content, embed, components) = match &reply {
+                SlashReply::Secret(result) => return respond_with_secret_roll(&ctx, command, &guild, result).await,
+                SlashReply::Public(content) | SlashReply::Private(content) => (content.clone(), None, None),
+                SlashReply::Roll(result, stored) => (result.content.clone(), result.embed.clone(), Some(stored.components())),
+                SlashReply::Character(assignment) => (assignment.content(), None, Some(assignment.components())),
+            };
+            let fitted = fit_reply(content);
+            let flags = match reply {
+                SlashReply::Private(_) => InteractionApplicationCommandCallbackDataFlags::EPHEMERAL,
+                _ => InteractionApplicationCommandCallbackDataFlags::empty(),
+            };
+            let result = command
+                .create_interaction_response(&ctx.http, |response| {
+                    response
+                        .kind(InteractionResponseType::ChannelMessageWithSource)
+                        .interaction_response_data(|data| {
+                            data.content(&fitted.content).flags(flags.clone()).embeds(embed);
+                            if let Some(components) = components {
+                                data.set_components(components);
+                            }
+                            data
+                        })
+                })
+                .await;
+            // interaction responses can't carry files, so anything too long follows up with the full reply
+            if let (Ok(()), Some(attachment)) = (&result, &fitted.attachment) {
+                let followup = command
+                    .create_followup_message(&ctx.http, |followup| {
+                        followup.add_file((attachment.as_bytes(), ATTACHMENT_FILENAME)).flags(flags)
+                    })
+                    .await;
+                if let Err(why) = followup {
+                    eprintln!("Error sending followup: {:?}", why);
+                }
+            }
+            if let Err(why) = result {
+                eprintln!("Error responding to interaction: {:?}", why);
+            } else if matches!(reply, SlashReply::Roll(..) | SlashReply::Character(_)) {
+                // buttons are tracked by message id, which is only known once the reply has been sent
+                match command.get_interaction_response(&ctx.http).await {
+                    Ok(sent) => match reply {
+                        SlashReply::Roll(_, stored) => store_roll(sent.id.0, stored),
+                        SlashReply::Character(assignment) => start_assignment(sent.id.0, assignment),
+                        _ => {}
+                    },
+                    Err(why) => eprintln!("Error fetching interaction response: {:?}", why),
+                }
+            }
+        }
+
+        if let Interaction::Autocomplete(autocomplete) = &interaction {
+            let settings = channel_settings(autocomplete.channel_id.0);
+            let choices = autocomplete_macros(&autocomplete.data, &settings);
+            let result = autocomplete
+                .create_autocomplete_response(&ctx.http, |response| {
+                    for (label, value) in choices {
+                        response.add_string_choice(label, value);
+                    }
+                    response
+                })
+                .await;
+            if let Err(why) = result {
+                eprintln!("Error responding to autocomplete: {:?}", why);
+            }
+        }
+
+        if let Interaction::MessageComponent(component) = interaction {
+            let custom_id = component.data.custom_id.as_str();
+            if is_roll_button(custom_id) {
+                let settings = channel_settings(component.channel_id.0);
+                let renderer = guild_settings(component.guild_id.map(|guild_id| guild_id.0)).renderer;
+                let reply =
+                    handle_roll_button(component.message.id.0, custom_id, &settings, renderer);
+                let (fitted, flags) = match &reply {
+                    RollButtonReply::Roll(result, _) => (
+                        fit_reply(result.content.clone()),
+                        InteractionApplicationCommandCallbackDataFlags::empty(),
+                    ),
+                    RollButtonReply::Private(content) => (
+                        fit_reply(content.clone()),
+                        InteractionApplicationCommandCallbackDataFlags::EPHEMERAL,
+                    ),
+                };
+                let result = component
+                    .create_interaction_response(&ctx.http, |response| {
+                        response
+                            .kind(InteractionResponseType::ChannelMessageWithSource)
+                            .interaction_response_data(|data| {
+                                data.content(&fitted.content).flags(flags.clone());
+                                match &reply {
+                                    RollButtonReply::Roll(result, stored) => {
+                                        data.embeds(result.embed.clone()).set_components(stored.components())
+                                    }
+                                    RollButtonReply::Private(_) => data,
+                                }
+                            })
+                    })
+                    .await;
+                if let (Ok(()), Some(attachment)) = (&result, &fitted.attachment) {
+                    let followup = component
+                        .create_followup_message(&ctx.http, |followup| {
+                            followup.add_file((attachment.as_bytes(), ATTACHMENT_FILENAME)).flags(flags)
+                        })
+                        .await;
+                    if let Err(why) = followup {
+                        eprintln!("Error sending followup: {:?}", why);
+                    }
+                }
+                match (result, reply) {
+                    (Err(why), _) => eprintln!("Error responding to interaction: {:?}", why),
+                    (Ok(()), RollButtonReply::Roll(_, stored)) => match component.get_interaction_response(&ctx.http).await {
+                        Ok(sent) => store_roll(sent.id.0, stored),
+                        Err(why) => eprintln!("Error fetching interaction response: {:?}", why),
+                    },
+                    (Ok(()), RollButtonReply::Private(_)) => {}
+                }
+            }
+
+            if custom_id.starts_with(ASSIGN_PREFIX) || custom_id == RACE_ID {
+                let reply = handle_assignment_component(
+                    component.message.id.0,
+                    component.user.id.0,
+                    custom_id,
+                    &component.data.values,
+                );
+                let result = component
+                    .create_interaction_response(&ctx.http, |response| match reply {
+                        AssignmentReply::Update(content, components) => response
+                            .kind(InteractionResponseType::UpdateMessage)
+                            .interaction_response_data(|data| data.content(content).set_components(components)),
+                        AssignmentReply::Private(content) => response
+                            .kind(InteractionResponseType::ChannelMessageWithSource)
+                            .interaction_response_data(|data| {
+                                data.content(content).flags(InteractionApplicationCommandCallbackDataFlags::EPHEMERAL)
+                            }),
+                    })
+                    .await;
+                if let Err(why) = result {
+                    eprintln!("Error responding to interaction: {:?}", why);
+                }
+            }
+        }
+    }
 }
 lazy_static! {
-    static ref RANDCHAR_RE: Regex = Regex::new(r"(?i)^(?:(?:rand(?:om)?)|(?:roll))\s*char(?:acter)?").unwrap();
+    static ref GM_RE: Regex = Regex::new(r"(?i)^dicebot\s+gm\s+(add|remove|list)\b(.*)").unwrap();
+    static ref MACRO_RE: Regex = Regex::new(r"(?is)^dicebot\s+macro\s+(.+)").unwrap();
+    static ref OUTPUT_RE: Regex = Regex::new(r"(?i)^dicebot\s+output\s+(\S+)").unwrap();
+    static ref MOVE_RE: Regex = Regex::new(r"(?is)^dicebot\s+move\s+(.+)").unwrap();
+    static ref PBTA_MODE_RE: Regex = Regex::new(r"(?i)^dicebot\s+pbta\s+(on|off)\b").unwrap();
+    static ref POINT_BUY_RE: Regex = Regex::new(r"(?i)^dicebot\s+point\s*buy\s+(.+)").unwrap();
+    static ref SYSTEM_RE: Regex = Regex::new(r"(?i)^dicebot\s+system\s+(\S+)").unwrap();
 }
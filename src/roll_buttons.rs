@@ -0,0 +1,161 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+};
+
+use serenity::{builder::CreateComponents, model::interactions::message_component::ButtonStyle};
+
+use crate::embed_renderer::{render_dice_rolls, RenderedRoll, Renderer};
+use crate::eval_dice_expression::{eval_dice_expression_result, format_breakdown, DiceExpressionResult};
+use crate::eval_pbta::eval_pbta;
+use crate::settings::ChannelSettings;
+
+// Custom ids of the buttons on a roll reply
+pub const ROLL_AGAIN_ID: &str = "roll_again";
+pub const ROLL_ADVANTAGE_ID: &str = "roll_advantage";
+pub const ROLL_BREAKDOWN_ID: &str = "roll_breakdown";
+
+// How many roll replies keep working buttons; older ones are forgotten first
+const MAX_STORED_ROLLS: usize = 1000;
+
+// What a roll reply's buttons need to know about the roll
+pub struct StoredRoll {
+    expression: String,
+    breakdown: String,
+    // rolls that already have advantage or disadvantage don't get the advantage button,
+    // since adding `adv` to a roll with `dis` would just cancel it out
+    advantage_button: bool,
+}
+
+#[derive(Default)]
+struct StoredRolls {
+    rolls: HashMap<u64, StoredRoll>,
+    // message ids, oldest first
+    order: VecDeque<u64>,
+}
+
+lazy_static! {
+    // Roll replies by message id
+    static ref STORED_ROLLS: Mutex<StoredRolls> = Mutex::new(StoredRolls::default());
+}
+
+// How to respond to a click on one of a roll reply's buttons
+pub enum RollButtonReply {
+    // Post a new roll, with buttons of its own
+    Roll(RenderedRoll, StoredRoll),
+    // Tell only the person who clicked
+    Private(String),
+}
+
+// Evaluates a dice expression, returning its reply along with what its buttons will need,
+// or just the reply if it can't have buttons (e.g. an error or a group of expressions)
+pub fn eval_roll_with_buttons(
+    expression: &str,
+    settings: &ChannelSettings,
+    renderer: Renderer,
+) -> Option<(RenderedRoll, Option<StoredRoll>)> {
+    // in PbtA mode, plain `2d6+N` rolls get outcome bands instead
+    if let Some(result) = eval_pbta(expression, settings.pbta_mode, &settings.pbta_moves) {
+        return Some((RenderedRoll::text(result), None));
+    }
+    match eval_dice_expression_result(expression, settings.roll_under_system)? {
+        DiceExpressionResult::Rolls(rolls) => {
+            let stored = StoredRoll {
+                expression: expression.trim().to_owned(),
+                breakdown: format_breakdown(expression, &rolls),
+                advantage_button: !rolls.advantage_given,
+            };
+            Some((render_dice_rolls(&rolls, renderer), Some(stored)))
+        }
+        DiceExpressionResult::Text(text) => Some((RenderedRoll::text(text), None)),
+    }
+}
+
+impl StoredRoll {
+    pub fn components(&self) -> CreateComponents {
+        let mut components = CreateComponents::default();
+        components.create_action_row(|action_row| {
+            action_row.create_button(|button| {
+                button.style(ButtonStyle::Primary).label("Roll again").custom_id(ROLL_AGAIN_ID)
+            });
+            if self.advantage_button {
+                action_row.create_button(|button| {
+                    button
+                        .style(ButtonStyle::Secondary)
+                        .label("Roll with advantage")
+                        .custom_id(ROLL_ADVANTAGE_ID)
+                });
+            }
+            action_row.create_button(|button| {
+                button.style(ButtonStyle::Secondary).label("Show breakdown").custom_id(ROLL_BREAKDOWN_ID)
+            })
+        });
+        components
+    }
+}
+
+// Remember a roll once its reply has been sent, so that the reply's buttons work
+pub fn store_roll(message_id: u64, roll: StoredRoll) {
+    let mut stored = STORED_ROLLS.lock().unwrap();
+    if stored.rolls.insert(message_id, roll).is_none() {
+        stored.order.push_back(message_id);
+    }
+    while stored.order.len() > MAX_STORED_ROLLS {
+        if let Some(oldest) = stored.order.pop_front() {
+            stored.rolls.remove(&oldest);
+        }
+    }
+}
+
+pub fn is_roll_button(custom_id: &str) -> bool {
+    [ROLL_AGAIN_ID, ROLL_ADVANTAGE_ID, ROLL_BREAKDOWN_ID].contains(&custom_id)
+}
+
+// Handle a click on one of a roll reply's buttons
+pub fn handle_roll_button(
+    message_id: u64,
+    custom_id: &str,
+    settings: &ChannelSettings,
+    renderer: Renderer,
+) -> RollButtonReply {
+    let expression = {
+        let stored = STORED_ROLLS.lock().unwrap();
+        match stored.rolls.get(&message_id) {
+            Some(roll) if custom_id == ROLL_BREAKDOWN_ID => return RollButtonReply::Private(roll.breakdown.clone()),
+            Some(roll) if custom_id == ROLL_ADVANTAGE_ID => format!("{} adv", roll.expression),
+            Some(roll) => roll.expression.clone(),
+            None => return RollButtonReply::Private("This roll is too old to reroll. Type it out again instead.".to_owned()),
+        }
+    };
+    match eval_roll_with_buttons(&expression, settings, renderer) {
+        Some((reply, Some(stored))) => RollButtonReply::Roll(reply, stored),
+        Some((reply, None)) => RollButtonReply::Private(reply.content),
+        None => RollButtonReply::Private(format!("Couldn't roll `{expression}` again.")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::embed_renderer::Renderer;
+    use crate::roll_buttons::eval_roll_with_buttons;
+    use crate::settings::ChannelSettings;
+
+    #[test]
+    fn no_advantage_button_once_advantage_is_given() {
+        let (_, stored) = eval_roll_with_buttons("1d20+5", &ChannelSettings::default(), Renderer::Text).unwrap();
+        assert!(stored.unwrap().advantage_button);
+        for expression in ["1d20+5 dis", "1d20+5 adv", "d20 a3", "1d20 adv dis"] {
+            let (_, stored) = eval_roll_with_buttons(expression, &ChannelSettings::default(), Renderer::Text).unwrap();
+            assert!(!stored.unwrap().advantage_button, "{expression}");
+        }
+    }
+    #[test]
+    fn errors_and_groups_have_no_buttons() {
+        let (_, stored) = eval_roll_with_buttons("1d20+5", &ChannelSettings::default(), Renderer::Text).unwrap();
+        assert!(stored.is_some());
+        let (_, stored) = eval_roll_with_buttons("1001d6", &ChannelSettings::default(), Renderer::Text).unwrap();
+        assert!(stored.is_none());
+        let (_, stored) = eval_roll_with_buttons("{1d20, 1d20}kh1", &ChannelSettings::default(), Renderer::Text).unwrap();
+        assert!(stored.is_none());
+    }
+}
@@ -0,0 +1,119 @@
+use serde::{Deserialize, Serialize};
+
+use crate::util::Outcome;
+
+// Rules for judging a roll-under roll such as `1d100 <= 45` or `3d6 <= 12`
+#[derive(Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RollUnderSystem {
+    // success or failure, with the margin
+    #[default]
+    Generic,
+    // regular/hard/extreme success, criticals on 01 and fumbles on 96–100
+    CallOfCthulhu,
+    // margin of success, with GURPS 4e critical success and critical failure ranges
+    Gurps,
+}
+
+impl RollUnderSystem {
+    // Parse a system keyword such as "coc" or "gurps"
+    pub fn from_keyword(keyword: &str) -> Option<RollUnderSystem> {
+        match keyword.to_lowercase().as_str() {
+            "generic" | "none" | "default" => Some(RollUnderSystem::Generic),
+            "coc" | "cthulhu" | "callofcthulhu" => Some(RollUnderSystem::CallOfCthulhu),
+            "gurps" => Some(RollUnderSystem::Gurps),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            RollUnderSystem::Generic => "generic",
+            RollUnderSystem::CallOfCthulhu => "Call of Cthulhu",
+            RollUnderSystem::Gurps => "GURPS",
+        }
+    }
+}
+
+// Judge how a roll of `total` fares against a roll-under `target` (e.g. a skill value),
+// along with a description of the outcome
+pub fn roll_under_outcome(system: RollUnderSystem, total: i64, target: i64) -> (Outcome, String) {
+    let margin = target - total;
+    match system {
+        RollUnderSystem::Generic => {
+            if total <= target {
+                (Outcome::Success, format!("Success (margin {margin})"))
+            } else {
+                (Outcome::Failure, format!("Failure (margin {margin})"))
+            }
+        }
+        RollUnderSystem::CallOfCthulhu => {
+            // 100 is always a fumble, and so is 96–99 when the skill is below 50
+            let is_fumble = total >= 100 || (target < 50 && total >= 96);
+            if total == 1 {
+                (Outcome::CriticalSuccess, "**Critical success!**".to_owned())
+            } else if is_fumble {
+                (Outcome::CriticalFailure, "**Fumble!**".to_owned())
+            } else if total <= target / 5 {
+                (Outcome::Success, "Extreme success".to_owned())
+            } else if total <= target / 2 {
+                (Outcome::Success, "Hard success".to_owned())
+            } else if total <= target {
+                (Outcome::Success, "Regular success".to_owned())
+            } else {
+                (Outcome::Failure, "Failure".to_owned())
+            }
+        }
+        RollUnderSystem::Gurps => {
+            // 3–4 always crit, 5 crits at skill 15+, and 6 at skill 16+
+            let is_critical_success = total <= 4
+                || (total == 5 && target >= 15)
+                || (total == 6 && target >= 16);
+            // 18 always crit fails, 17 does at skill 15 or less, and so does missing by 10 or more
+            let is_critical_failure =
+                total >= 18 || (total == 17 && target <= 15) || margin <= -10;
+            if is_critical_success {
+                (Outcome::CriticalSuccess, format!("**Critical success!** (margin {margin})"))
+            } else if is_critical_failure {
+                (Outcome::CriticalFailure, format!("**Critical failure!** (margin {margin})"))
+            } else if total <= target && total < 17 {
+                (Outcome::Success, format!("Success (margin {margin})"))
+            } else {
+                (Outcome::Failure, format!("Failure (margin {margin})"))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::roll_under::{roll_under_outcome, RollUnderSystem};
+    use crate::util::Outcome;
+
+    #[test]
+    fn cthulhu_success_levels() {
+        let system = RollUnderSystem::CallOfCthulhu;
+        assert_eq!(roll_under_outcome(system, 1, 45).1, "**Critical success!**");
+        assert_eq!(roll_under_outcome(system, 9, 45).1, "Extreme success");
+        assert_eq!(roll_under_outcome(system, 22, 45).1, "Hard success");
+        assert_eq!(roll_under_outcome(system, 45, 45).1, "Regular success");
+        assert_eq!(roll_under_outcome(system, 46, 45).1, "Failure");
+    }
+    #[test]
+    fn cthulhu_fumbles() {
+        let system = RollUnderSystem::CallOfCthulhu;
+        assert_eq!(roll_under_outcome(system, 96, 45).0, Outcome::CriticalFailure);
+        assert_eq!(roll_under_outcome(system, 96, 45).1, "**Fumble!**");
+        assert_eq!(roll_under_outcome(system, 96, 60).1, "Failure");
+        assert_eq!(roll_under_outcome(system, 100, 60).1, "**Fumble!**");
+    }
+    #[test]
+    fn gurps_criticals() {
+        let system = RollUnderSystem::Gurps;
+        assert_eq!(roll_under_outcome(system, 4, 8).1, "**Critical success!** (margin 4)");
+        assert_eq!(roll_under_outcome(system, 6, 16).1, "**Critical success!** (margin 10)");
+        assert_eq!(roll_under_outcome(system, 6, 15).1, "Success (margin 9)");
+        assert_eq!(roll_under_outcome(system, 17, 15).1, "**Critical failure!** (margin -2)");
+        assert_eq!(roll_under_outcome(system, 17, 16).1, "Failure (margin -1)");
+        assert_eq!(roll_under_outcome(system, 15, 5).1, "**Critical failure!** (margin -10)");
+    }
+}
@@ -0,0 +1,378 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use regex::Regex;
+use serenity::{
+    client::Context,
+    model::{
+        id::{ChannelId, GuildId, RoleId, UserId},
+        interactions::{
+            application_command::ApplicationCommandInteraction, InteractionApplicationCommandCallbackDataFlags,
+            InteractionResponseType,
+        },
+        permissions::Permissions,
+    },
+};
+
+use crate::long_reply::{fit_reply, ATTACHMENT_FILENAME};
+use crate::settings::GuildSettings;
+
+// Discord returns at most this many members per request
+const MEMBERS_PAGE_SIZE: u64 = 1000;
+// Stop looking for members with a GM role after this many requests, to stay clear of rate limits on huge servers
+const MAX_MEMBER_PAGES: usize = 5;
+// How long the members with a GM role are remembered before they're listed again
+const ROLE_MEMBERS_TTL: Duration = Duration::from_secs(10 * 60);
+
+// Listing a server's members, and so finding who has a GM role, needs the privileged Server Members intent
+pub const MEMBERS_INTENT_NOTE: &str = "I couldn't see who has the GM roles, so only GMs added by name get secret rolls. \
+    Role GMs need the Server Members Intent, which the bot's owner can turn on in the Discord Developer Portal.";
+
+pub const TOO_MANY_MEMBERS_NOTE: &str = "This server has too many members for me to check them all for the GM roles, \
+    so some role GMs may not get secret rolls. Add them by name with `dicebot gm add @user` instead.";
+
+// How finding the members with a GM role went
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RoleLookup {
+    Complete,
+    // the members couldn't be listed at all
+    MissingIntent,
+    // only the first MAX_MEMBER_PAGES pages of members were checked
+    TooManyMembers,
+}
+
+impl RoleLookup {
+    // What to tell the server about it, if anything
+    pub fn note(self) -> Option<&'static str> {
+        match self {
+            RoleLookup::Complete => None,
+            RoleLookup::MissingIntent => Some(MEMBERS_INTENT_NOTE),
+            RoleLookup::TooManyMembers => Some(TOO_MANY_MEMBERS_NOTE),
+        }
+    }
+}
+
+// Who a secret roll reached
+pub struct SecretRollDelivery {
+    pub to_roller: bool,
+    pub gm_roles: RoleLookup,
+}
+
+// The members found with a server's GM roles
+struct RoleMembers {
+    gm_roles: BTreeSet<u64>,
+    user_ids: BTreeSet<u64>,
+    lookup: RoleLookup,
+    fetched_at: Instant,
+}
+
+// Listing members takes a request per thousand of them, so each server's are remembered for a while
+#[derive(Default)]
+struct RoleMembersCache {
+    guilds: HashMap<u64, RoleMembers>,
+}
+
+impl RoleMembersCache {
+    // The remembered members, unless they're stale or were found for different GM roles
+    fn get(&self, guild_id: u64, gm_roles: &BTreeSet<u64>, now: Instant) -> Option<(BTreeSet<u64>, RoleLookup)> {
+        self.guilds
+            .get(&guild_id)
+            .filter(|members| &members.gm_roles == gm_roles && now.duration_since(members.fetched_at) < ROLE_MEMBERS_TTL)
+            .map(|members| (members.user_ids.clone(), members.lookup))
+    }
+
+    fn insert(&mut self, guild_id: u64, members: RoleMembers) {
+        let now = members.fetched_at;
+        self.guilds.retain(|_, members| now.duration_since(members.fetched_at) < ROLE_MEMBERS_TTL);
+        self.guilds.insert(guild_id, members);
+    }
+}
+
+lazy_static! {
+    static ref SECRET_RE: Regex = Regex::new(r"(?is)^secret\s+(.+)").unwrap();
+    static ref MENTION_RE: Regex = Regex::new(r"<@([!&]?)(\d+)>").unwrap();
+    static ref ROLE_MEMBERS: Mutex<RoleMembersCache> = Mutex::new(RoleMembersCache::default());
+}
+
+// The expression in a secret roll such as `secret 1d20+5`
+pub fn parse_secret_roll(message: &str) -> Option<&str> {
+    SECRET_RE.captures(message).map(|captures| captures.get(1).unwrap().as_str().trim())
+}
+
+// What the channel sees in place of the result
+pub fn secret_roll_announcement(user_name: &str, delivery: &SecretRollDelivery) -> String {
+    let announcement = if delivery.to_roller {
+        format!("{user_name} made a secret roll.")
+    } else {
+        format!("{user_name} made a secret roll, but I couldn't DM them the result. Check that direct messages from server members are allowed.")
+    };
+    match delivery.gm_roles.note() {
+        Some(note) => format!("{announcement}\n{note}"),
+        None => announcement,
+    }
+}
+
+// The users and roles mentioned in e.g. `dicebot gm add @Alice @GM`, as (user ids, role ids)
+pub fn parse_gm_mentions(text: &str) -> (Vec<u64>, Vec<u64>) {
+    let mut users = vec![];
+    let mut roles = vec![];
+    for captures in MENTION_RE.captures_iter(text) {
+        if let Ok(id) = captures[2].parse() {
+            if &captures[1] == "&" {
+                roles.push(id);
+            } else {
+                users.push(id);
+            }
+        }
+    }
+    (users, roles)
+}
+
+// Whether someone can change a server's GMs: its owner, or anyone with Manage Server (or Administrator)
+// on @everyone or one of their roles
+pub fn can_manage_gms(is_owner: bool, role_permissions: &[Permissions]) -> bool {
+    is_owner
+        || role_permissions
+            .iter()
+            .any(|permissions| permissions.intersects(Permissions::MANAGE_GUILD | Permissions::ADMINISTRATOR))
+}
+
+// Like can_manage_gms, fetching the server for its owner and role permissions
+pub async fn member_can_manage_gms(ctx: &Context, guild_id: GuildId, user_id: UserId, member_roles: &[RoleId]) -> bool {
+    let guild = match guild_id.to_partial_guild(&ctx.http).await {
+        Ok(guild) => guild,
+        Err(why) => {
+            eprintln!("Error fetching guild: {:?}", why);
+            return false;
+        }
+    };
+    // the @everyone role shares the server's id
+    let role_permissions: Vec<Permissions> = std::iter::once(&RoleId(guild_id.0))
+        .chain(member_roles)
+        .filter_map(|role| guild.roles.get(role))
+        .map(|role| role.permissions)
+        .collect();
+    can_manage_gms(guild.owner_id == user_id, &role_permissions)
+}
+
+// Whether the server's members can be listed, which GM roles need
+pub async fn can_list_members(ctx: &Context, guild_id: GuildId) -> bool {
+    match guild_id.members(&ctx.http, Some(1), None).await {
+        Ok(_) => true,
+        Err(why) => {
+            eprintln!("Error fetching guild members: {:?}", why);
+            false
+        }
+    }
+}
+
+// Everyone a secret roll goes to besides the roller: the GM users, and the members with a GM role
+async fn gm_user_ids(ctx: &Context, guild_id: GuildId, settings: &GuildSettings) -> (BTreeSet<u64>, RoleLookup) {
+    if settings.gm_roles.is_empty() {
+        return (settings.gm_users.clone(), RoleLookup::Complete);
+    }
+    let (role_members, lookup) = role_member_ids(ctx, guild_id, &settings.gm_roles).await;
+    (settings.gm_users.union(&role_members).copied().collect(), lookup)
+}
+
+// The members with any of the GM roles. Listing them needs the Server Members intent
+async fn role_member_ids(ctx: &Context, guild_id: GuildId, gm_roles: &BTreeSet<u64>) -> (BTreeSet<u64>, RoleLookup) {
+    if let Some(cached) = ROLE_MEMBERS.lock().unwrap().get(guild_id.0, gm_roles, Instant::now()) {
+        return cached;
+    }
+    let mut user_ids = BTreeSet::new();
+    let mut lookup = RoleLookup::TooManyMembers;
+    let mut after = None;
+    for _ in 0..MAX_MEMBER_PAGES {
+        let members = match guild_id.members(&ctx.http, Some(MEMBERS_PAGE_SIZE), after).await {
+            Ok(members) => members,
+            Err(why) => {
+                // not remembered, so that turning the intent on takes effect right away
+                eprintln!("Error fetching guild members: {:?}", why);
+                return (user_ids, RoleLookup::MissingIntent);
+            }
+        };
+        for member in &members {
+            if gm_roles.iter().any(|role| member.roles.contains(&RoleId(*role))) {
+                user_ids.insert(member.user.id.0);
+            }
+        }
+        match members.last() {
+            Some(last) if members.len() as u64 == MEMBERS_PAGE_SIZE => after = Some(last.user.id),
+            _ => {
+                lookup = RoleLookup::Complete;
+                break;
+            }
+        }
+    }
+    let members = RoleMembers {
+        gm_roles: gm_roles.clone(),
+        user_ids: user_ids.clone(),
+        lookup,
+        fetched_at: Instant::now(),
+    };
+    ROLE_MEMBERS.lock().unwrap().insert(guild_id.0, members);
+    (user_ids, lookup)
+}
+
+async fn send_dm(ctx: &Context, user_id: u64, content: &str) -> bool {
+    let fitted = fit_reply(content.to_owned());
+    let sent = match UserId(user_id).create_dm_channel(ctx).await {
+        Ok(channel) => {
+            channel
+                .send_message(&ctx.http, |message| {
+                    message
+                        .content(&fitted.content)
+                        .add_files(fitted.attachment.as_ref().map(|text| (text.as_bytes(), ATTACHMENT_FILENAME)))
+                })
+                .await
+        }
+        Err(why) => Err(why),
+    };
+    if let Err(why) = &sent {
+        eprintln!("Error sending direct message: {:?}", why);
+    }
+    sent.is_ok()
+}
+
+// DM a secret roll's result to the roller and the guild's GMs
+pub async fn deliver_secret_roll(
+    ctx: &Context,
+    guild_id: Option<GuildId>,
+    channel_id: ChannelId,
+    roller: (u64, &str),
+    settings: &GuildSettings,
+    result: &str,
+) -> SecretRollDelivery {
+    let (roller_id, roller_name) = roller;
+    let to_roller = send_dm(ctx, roller_id, &format!("Your secret roll in <#{channel_id}>:\n{result}")).await;
+    let mut gm_roles = RoleLookup::Complete;
+    if let Some(guild_id) = guild_id {
+        let gm_content = format!("**{roller_name}** made a secret roll in <#{channel_id}>:\n{result}");
+        let (user_ids, lookup) = gm_user_ids(ctx, guild_id, settings).await;
+        gm_roles = lookup;
+        for user_id in user_ids {
+            if user_id != roller_id {
+                send_dm(ctx, user_id, &gm_content).await;
+            }
+        }
+    }
+    SecretRollDelivery { to_roller, gm_roles }
+}
+
+// Respond to a `/secret` command: announce the roll, then DM it to the roller and the guild's GMs
+pub async fn respond_with_secret_roll(
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+    settings: &GuildSettings,
+    result: &str,
+) {
+    // announce first, as sending the DMs can take longer than Discord waits for a response
+    let announced = command
+        .create_interaction_response(&ctx.http, |response| {
+            response
+                .kind(InteractionResponseType::ChannelMessageWithSource)
+                .interaction_response_data(|data| {
+                    let delivery = SecretRollDelivery { to_roller: true, gm_roles: RoleLookup::Complete };
+                    data.content(secret_roll_announcement(&command.user.name, &delivery))
+                })
+        })
+        .await;
+    if let Err(why) = announced {
+        eprintln!("Error responding to interaction: {:?}", why);
+        return;
+    }
+    let roller = (command.user.id.0, command.user.name.as_str());
+    let delivery = deliver_secret_roll(ctx, command.guild_id, command.channel_id, roller, settings, result).await;
+    if let Some(note) = delivery.gm_roles.note() {
+        let followup = command
+            .create_followup_message(&ctx.http, |followup| followup.content(note))
+            .await;
+        if let Err(why) = followup {
+            eprintln!("Error sending followup: {:?}", why);
+        }
+    }
+    if !delivery.to_roller {
+        // the roller's DMs are closed, so show them the result where only they can see it
+        let fitted = fit_reply(result.to_owned());
+        let followup = command
+            .create_followup_message(&ctx.http, |followup| {
+                followup
+                    .content(&fitted.content)
+                    .add_files(fitted.attachment.as_ref().map(|text| (text.as_bytes(), ATTACHMENT_FILENAME)))
+                    .flags(InteractionApplicationCommandCallbackDataFlags::EPHEMERAL)
+            })
+            .await;
+        if let Err(why) = followup {
+            eprintln!("Error sending followup: {:?}", why);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::BTreeSet,
+        time::{Duration, Instant},
+    };
+
+    use serenity::model::permissions::Permissions;
+
+    use crate::secret_roll::{
+        can_manage_gms, parse_gm_mentions, parse_secret_roll, secret_roll_announcement, RoleLookup, RoleMembers,
+        RoleMembersCache, SecretRollDelivery, MEMBERS_INTENT_NOTE, ROLE_MEMBERS_TTL, TOO_MANY_MEMBERS_NOTE,
+    };
+
+    #[test]
+    fn secret_rolls() {
+        assert_eq!(parse_secret_roll("secret 1d20+5"), Some("1d20+5"));
+        assert_eq!(parse_secret_roll("Secret  4d6k3 "), Some("4d6k3"));
+        assert_eq!(parse_secret_roll("1d20 secret"), None);
+    }
+    #[test]
+    fn gm_mentions() {
+        assert_eq!(parse_gm_mentions("<@12> <@!34> <@&56> @everyone"), (vec![12, 34], vec![56]));
+    }
+    #[test]
+    fn only_owners_and_managers_change_gms() {
+        assert!(!can_manage_gms(false, &[]));
+        assert!(!can_manage_gms(false, &[Permissions::SEND_MESSAGES, Permissions::MANAGE_MESSAGES]));
+        assert!(can_manage_gms(false, &[Permissions::SEND_MESSAGES, Permissions::MANAGE_GUILD]));
+        assert!(can_manage_gms(false, &[Permissions::ADMINISTRATOR]));
+        assert!(can_manage_gms(true, &[]));
+    }
+    #[test]
+    fn announcements_mention_the_members_intent() {
+        let delivery = SecretRollDelivery { to_roller: true, gm_roles: RoleLookup::Complete };
+        assert_eq!(secret_roll_announcement("Alice", &delivery), "Alice made a secret roll.");
+        let delivery = SecretRollDelivery { to_roller: true, gm_roles: RoleLookup::MissingIntent };
+        assert_eq!(secret_roll_announcement("Alice", &delivery), format!("Alice made a secret roll.\n{MEMBERS_INTENT_NOTE}"));
+        assert!(MEMBERS_INTENT_NOTE.contains("Server Members Intent"));
+        let delivery = SecretRollDelivery { to_roller: true, gm_roles: RoleLookup::TooManyMembers };
+        assert_eq!(secret_roll_announcement("Alice", &delivery), format!("Alice made a secret roll.\n{TOO_MANY_MEMBERS_NOTE}"));
+    }
+    #[test]
+    fn role_members_are_remembered_for_a_while() {
+        let mut cache = RoleMembersCache::default();
+        let gm_roles = BTreeSet::from([56]);
+        let fetched_at = Instant::now();
+        cache.insert(
+            1,
+            RoleMembers {
+                gm_roles: gm_roles.clone(),
+                user_ids: BTreeSet::from([12]),
+                lookup: RoleLookup::Complete,
+                fetched_at,
+            },
+        );
+        let soon = fetched_at + Duration::from_secs(60);
+        assert_eq!(cache.get(1, &gm_roles, soon), Some((BTreeSet::from([12]), RoleLookup::Complete)));
+        // changing the GM roles or waiting too long means listing the members again
+        assert_eq!(cache.get(1, &BTreeSet::from([56, 78]), soon), None);
+        assert_eq!(cache.get(1, &gm_roles, fetched_at + ROLE_MEMBERS_TTL), None);
+        assert_eq!(cache.get(2, &gm_roles, soon), None);
+    }
+}
@@ -0,0 +1,116 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs::{read_to_string, write},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, RwLock,
+    },
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::embed_renderer::Renderer;
+use crate::eval_pbta::PbtaMove;
+use crate::eval_point_buy::PointBuyRules;
+use crate::roll_under::RollUnderSystem;
+
+const SETTINGS_FILENAME: &str = "./settings.json";
+
+// Per-channel configuration, changed with `dicebot` commands
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChannelSettings {
+    // the rules used for roll-under rolls (e.g. `1d100 <= 45`) that don't name a system
+    pub roll_under_system: RollUnderSystem,
+    // whether plain `2d6+N` rolls get Powered by the Apocalypse outcome bands, like `pbta +N`
+    pub pbta_mode: bool,
+    // custom outcome text for Powered by the Apocalypse moves, keyed by lowercase move name
+    pub pbta_moves: HashMap<String, PbtaMove>,
+    // the budget and costs that `pointbuy` checks arrays against
+    pub point_buy: PointBuyRules,
+    // saved roll expressions for `/roll`, keyed by lowercase macro name
+    pub macros: HashMap<String, String>,
+}
+
+// Per-guild configuration, changed with `dicebot` commands
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GuildSettings {
+    // whether dice expression replies are sent as plain text or as embeds
+    pub renderer: Renderer,
+    // users who are sent every secret roll, along with anyone who has one of the roles
+    pub gm_users: BTreeSet<u64>,
+    pub gm_roles: BTreeSet<u64>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+struct Settings {
+    channels: HashMap<u64, ChannelSettings>,
+    guilds: HashMap<u64, GuildSettings>,
+}
+
+lazy_static! {
+    // Loaded once at startup and saved back to the settings file whenever it changes
+    static ref SETTINGS: RwLock<Settings> = RwLock::new(load_settings());
+    // The version of the settings last written to the file, so that a slow write can't overwrite a newer one
+    static ref SAVED_VERSION: Mutex<u64> = Mutex::new(0);
+}
+
+static LATEST_VERSION: AtomicU64 = AtomicU64::new(0);
+
+fn load_settings() -> Settings {
+    match read_to_string(SETTINGS_FILENAME) {
+        Ok(json) => serde_json::from_str(&json).unwrap_or_else(|e| {
+            eprintln!("ERROR: Failed to parse {SETTINGS_FILENAME}, using default settings: {e:?}");
+            Settings::default()
+        }),
+        Err(_) => Settings::default(),
+    }
+}
+
+// Called with the settings still locked, so that each version is serialized as it was changed.
+// The file is written on a blocking thread instead, so neither the lock nor the async handler waits on it
+fn save_settings(settings: &Settings) {
+    let json = match serde_json::to_string_pretty(settings) {
+        Ok(json) => json,
+        Err(e) => {
+            eprintln!("ERROR: Failed to serialize settings: {e:?}");
+            return;
+        }
+    };
+    let version = LATEST_VERSION.fetch_add(1, Ordering::SeqCst) + 1;
+    tokio::task::spawn_blocking(move || {
+        let mut saved_version = SAVED_VERSION.lock().unwrap();
+        if *saved_version > version {
+            return;
+        }
+        if let Err(e) = write(SETTINGS_FILENAME, json) {
+            eprintln!("ERROR: Failed to save {SETTINGS_FILENAME}: {e:?}");
+        }
+        *saved_version = version;
+    });
+}
+
+pub fn channel_settings(channel_id: u64) -> ChannelSettings {
+    let settings = SETTINGS.read().unwrap();
+    settings.channels.get(&channel_id).cloned().unwrap_or_default()
+}
+
+pub fn update_channel_settings<F: FnOnce(&mut ChannelSettings)>(channel_id: u64, update: F) {
+    let mut settings = SETTINGS.write().unwrap();
+    update(settings.channels.entry(channel_id).or_default());
+    save_settings(&settings);
+}
+
+// Direct messages have no guild, so they always get the default settings
+pub fn guild_settings(guild_id: Option<u64>) -> GuildSettings {
+    let settings = SETTINGS.read().unwrap();
+    guild_id.and_then(|guild_id| settings.guilds.get(&guild_id).cloned()).unwrap_or_default()
+}
+
+pub fn update_guild_settings<F: FnOnce(&mut GuildSettings)>(guild_id: u64, update: F) {
+    let mut settings = SETTINGS.write().unwrap();
+    update(settings.guilds.entry(guild_id).or_default());
+    save_settings(&settings);
+}
@@ -0,0 +1,225 @@
+use serenity::{
+    builder::{CreateApplicationCommandOption, CreateApplicationCommands},
+    model::interactions::application_command::{ApplicationCommandInteractionData, ApplicationCommandOptionType},
+};
+
+use crate::embed_renderer::{RenderedRoll, Renderer};
+use crate::eval_character::{generate_character, GenerationMethod};
+use crate::eval_roll::eval_roll;
+use crate::roll_buttons::{eval_roll_with_buttons, StoredRoll};
+use crate::settings::ChannelSettings;
+use crate::stat_assignment::StatAssignment;
+
+// Longest expression `/roll` will evaluate, well beyond anything typed by hand
+const MAX_EXPRESSION_LENGTH: usize = 200;
+
+// How to respond to a slash command
+pub enum SlashReply {
+    // Reply in the channel
+    Public(String),
+    // Tell only the person who used the command
+    Private(String),
+    // Reply with a roll and buttons for rolling it again
+    Roll(RenderedRoll, StoredRoll),
+    // Reply with rolled scores and buttons for assigning them
+    Character(StatAssignment),
+    // DM the result to the roller and the GMs, and tell the channel only that a roll was made
+    Secret(String),
+}
+
+fn expression_option(option: &mut CreateApplicationCommandOption) -> &mut CreateApplicationCommandOption {
+    option
+        .name("expression")
+        .description("What to roll, or the name of a macro saved with `dicebot macro`")
+        .kind(ApplicationCommandOptionType::String)
+        .required(true)
+        .set_autocomplete(true)
+}
+
+fn method_option(option: &mut CreateApplicationCommandOption) -> &mut CreateApplicationCommandOption {
+    option
+        .name("method")
+        .description("How to roll each score (4d6, dropping the lowest, by default)")
+        .kind(ApplicationCommandOptionType::String)
+        .add_string_choice("4d6, dropping the lowest", "4d6k3")
+        .add_string_choice("4d6, rerolling 1s and dropping the lowest", "4d6r1k3")
+        .add_string_choice("3d6 in order", "3d6")
+        .add_string_choice("2d6+6", "2d6+6")
+}
+
+// The commands registered with Discord when the bot starts
+pub fn create_slash_commands(commands: &mut CreateApplicationCommands) -> &mut CreateApplicationCommands {
+    commands
+        .create_application_command(|command| {
+            command
+                .name("roll")
+                .description("Roll dice, e.g. 1d20+5, 4d6k3*6 or a saved macro")
+                .create_option(expression_option)
+        })
+        .create_application_command(|command| {
+            command
+                .name("gmroll")
+                .description("Roll dice in secret, sending the result only to you and the GMs")
+                .create_option(expression_option)
+        })
+        .create_application_command(|command| {
+            command
+                .name("stats")
+                .description("Roll six ability scores")
+                .create_option(method_option)
+                .create_option(|option| {
+                    option
+                        .name("min")
+                        .description("Reroll the scores until their modifiers add up to at least this")
+                        .kind(ApplicationCommandOptionType::Integer)
+                        .min_int_value(-24)
+                        .max_int_value(24)
+                })
+        })
+        .create_application_command(|command| {
+            command
+                .name("char")
+                .description("Roll a character and assign its scores to abilities")
+                .create_option(method_option)
+        })
+}
+
+fn string_option<'a>(data: &'a ApplicationCommandInteractionData, name: &str) -> Option<&'a str> {
+    data.options
+        .iter()
+        .find(|option| option.name == name)
+        .and_then(|option| option.value.as_ref())
+        .and_then(|value| value.as_str())
+}
+
+fn int_option(data: &ApplicationCommandInteractionData, name: &str) -> Option<i64> {
+    data.options
+        .iter()
+        .find(|option| option.name == name)
+        .and_then(|option| option.value.as_ref())
+        .and_then(|value| value.as_i64())
+}
+
+// The expression `/roll` was given, with a saved macro swapped for its expression and name
+fn expand_macro<'a>(data: &'a ApplicationCommandInteractionData, settings: &'a ChannelSettings) -> Result<(Option<String>, &'a str), String> {
+    let expression = string_option(data, "expression").unwrap_or_default().trim();
+    if expression.len() > MAX_EXPRESSION_LENGTH {
+        return Err(format!("That expression is too long. Keep it under {MAX_EXPRESSION_LENGTH} characters."));
+    }
+    Ok(match settings.macros.get(&expression.to_lowercase()) {
+        Some(macro_expression) => (Some(format!("**{expression}**")), macro_expression.as_str()),
+        None => (None, expression),
+    })
+}
+
+fn unknown_expression(expression: &str) -> String {
+    format!("`{expression}` isn't something I know how to roll. Try something like `1d20+5` or `4d6k3*6`.")
+}
+
+pub fn eval_slash_command(
+    data: &ApplicationCommandInteractionData,
+    user_id: u64,
+    settings: &ChannelSettings,
+    renderer: Renderer,
+) -> SlashReply {
+    let method = string_option(data, "method")
+        .and_then(GenerationMethod::from_keyword)
+        .unwrap_or(GenerationMethod::FourDropLowest);
+    match data.name.as_str() {
+        "roll" => {
+            // a saved macro rolls its expression, with the macro's name in front
+            let (label, expression) = match expand_macro(data, settings) {
+                Ok(expanded) => expanded,
+                Err(error) => return SlashReply::Private(error),
+            };
+            match eval_roll_with_buttons(expression, settings, renderer) {
+                Some((result, Some(stored))) => {
+                    let result = match &label {
+                        Some(label) => result.with_label(label),
+                        None => result,
+                    };
+                    return SlashReply::Roll(result, stored);
+                }
+                // groups, PbtA rolls and errors have no buttons, but are already rolled
+                Some((result, None)) => {
+                    return SlashReply::Public(match label {
+                        Some(label) => format!("{label}: {}", result.content),
+                        None => result.content,
+                    })
+                }
+                None => {}
+            }
+            match eval_roll(expression, user_id, settings) {
+                Some(result) => SlashReply::Public(match label {
+                    Some(label) => format!("{label}: {result}"),
+                    None => result,
+                }),
+                None => SlashReply::Private(unknown_expression(expression)),
+            }
+        }
+        "gmroll" => {
+            let (label, expression) = match expand_macro(data, settings) {
+                Ok(expanded) => expanded,
+                Err(error) => return SlashReply::Private(error),
+            };
+            match eval_roll(expression, user_id, settings) {
+                Some(result) => SlashReply::Secret(match label {
+                    Some(label) => format!("{label}: {result}"),
+                    None => result,
+                }),
+                None => SlashReply::Private(unknown_expression(expression)),
+            }
+        }
+        "stats" => {
+            let threshold = int_option(data, "min").map(|threshold| threshold.clamp(-24, 24) as i32);
+            SlashReply::Public(generate_character(method, threshold).0)
+        }
+        "char" => {
+            let (reply, scores) = generate_character(method, None);
+            SlashReply::Character(StatAssignment::new(&scores, user_id, reply))
+        }
+        _ => SlashReply::Private("Unknown command.".to_owned()),
+    }
+}
+
+// Suggestions for `/roll`'s expression as it's typed: the saved macros whose names contain what's been typed so far.
+// Returns (label, value) pairs, at most 25 of them as that's all Discord will show
+pub fn autocomplete_macros(data: &ApplicationCommandInteractionData, settings: &ChannelSettings) -> Vec<(String, String)> {
+    let typed = string_option(data, "expression").unwrap_or_default().to_lowercase();
+    let mut names: Vec<&String> = settings.macros.keys().filter(|name| name.contains(&typed)).collect();
+    names.sort();
+    names
+        .into_iter()
+        .take(25)
+        .map(|name| {
+            // choice labels are limited to 100 characters
+            let label: String = format!("{name}: {}", settings.macros[name]).chars().take(100).collect();
+            (label, name.clone())
+        })
+        .collect()
+}
+
+// Parses a macro definition such as `fireball: 8d6`. An empty expression deletes the macro
+pub fn parse_macro(definition: &str) -> Option<(String, String)> {
+    let (name, expression) = definition.split_once(':')?;
+    let name = name.trim().to_lowercase();
+    // Discord limits autocomplete values to 100 characters
+    if name.is_empty() || name.chars().count() > 100 || expression.trim().len() > MAX_EXPRESSION_LENGTH {
+        return None;
+    }
+    Some((name, expression.trim().to_owned()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::slash_commands::parse_macro;
+
+    #[test]
+    fn macro_definitions() {
+        assert_eq!(parse_macro("Fireball : 8d6"), Some(("fireball".to_owned(), "8d6".to_owned())));
+        // an empty expression deletes the macro
+        assert_eq!(parse_macro("fireball:"), Some(("fireball".to_owned(), "".to_owned())));
+        assert_eq!(parse_macro(": 8d6"), None);
+        assert_eq!(parse_macro("fireball 8d6"), None);
+    }
+}
@@ -0,0 +1,328 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+};
+
+use serenity::{builder::CreateComponents, model::interactions::message_component::ButtonStyle};
+
+use crate::eval_character::{ability_modifier, format_ability_modifier, AbilityScores, GenerationMethod, ABILITIES};
+
+// Prefixes of the custom ids on the assignment message's components, so interactions can be routed back here
+pub const ASSIGN_PREFIX: &str = "assign_stat:";
+pub const RACE_ID: &str = "assign_race";
+const RESET_ID: &str = "assign_stat:reset";
+
+// How many characters can be assigned at once; the oldest are forgotten first
+const MAX_ASSIGNMENTS: usize = 1000;
+
+// Racial ability score increases from the 5e SRD
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Race {
+    Human,
+    Dwarf,
+    Elf,
+    Halfling,
+    Dragonborn,
+    Gnome,
+    HalfElf,
+    HalfOrc,
+    Tiefling,
+    Flexible,
+    NoBonuses,
+}
+
+const RACES: [Race; 11] = [
+    Race::Human,
+    Race::Dwarf,
+    Race::Elf,
+    Race::Halfling,
+    Race::Dragonborn,
+    Race::Gnome,
+    Race::HalfElf,
+    Race::HalfOrc,
+    Race::Tiefling,
+    Race::Flexible,
+    Race::NoBonuses,
+];
+
+impl Race {
+    fn name(self) -> &'static str {
+        match self {
+            Race::Human => "Human",
+            Race::Dwarf => "Dwarf",
+            Race::Elf => "Elf",
+            Race::Halfling => "Halfling",
+            Race::Dragonborn => "Dragonborn",
+            Race::Gnome => "Gnome",
+            Race::HalfElf => "Half-Elf",
+            Race::HalfOrc => "Half-Orc",
+            Race::Tiefling => "Tiefling",
+            Race::Flexible => "Background",
+            Race::NoBonuses => "No bonuses",
+        }
+    }
+
+    fn description(self) -> &'static str {
+        match self {
+            Race::Human => "+1 to every ability",
+            Race::Dwarf => "CON +2",
+            Race::Elf | Race::Halfling => "DEX +2",
+            Race::Dragonborn => "STR +2, CHA +1",
+            Race::Gnome => "INT +2",
+            Race::HalfElf => "CHA +2, +1 to your two highest other abilities",
+            Race::HalfOrc => "STR +2, CON +1",
+            Race::Tiefling => "CHA +2, INT +1",
+            Race::Flexible => "+2 to your highest ability, +1 to the next highest",
+            Race::NoBonuses => "Keep the scores as rolled",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Race> {
+        RACES.iter().copied().find(|race| race.name() == name)
+    }
+
+    // The increase to each ability, in the order of `ABILITIES`.
+    // The choices that Half-Elves and backgrounds get go to the highest scores, which is what most players pick
+    fn bonuses(self, scores: &[i32; 6]) -> [i32; 6] {
+        let highest_first = |skip: Option<usize>| {
+            let mut order: Vec<usize> = (0..6).filter(|i| Some(*i) != skip).collect();
+            order.sort_by_key(|i| -scores[*i]);
+            order
+        };
+        let mut bonuses = [0; 6];
+        match self {
+            Race::Human => bonuses = [1; 6],
+            Race::Dwarf => bonuses[2] = 2,
+            Race::Elf | Race::Halfling => bonuses[1] = 2,
+            Race::Dragonborn => bonuses = [2, 0, 0, 0, 0, 1],
+            Race::Gnome => bonuses[3] = 2,
+            Race::HalfElf => {
+                bonuses[5] = 2;
+                for i in highest_first(Some(5)).into_iter().take(2) {
+                    bonuses[i] = 1;
+                }
+            }
+            Race::HalfOrc => bonuses = [2, 0, 1, 0, 0, 0],
+            Race::Tiefling => bonuses = [0, 0, 0, 1, 0, 2],
+            Race::Flexible => {
+                let order = highest_first(None);
+                bonuses[order[0]] = 2;
+                bonuses[order[1]] = 1;
+            }
+            Race::NoBonuses => {}
+        }
+        bonuses
+    }
+}
+
+// A player's progress through assigning their rolled scores to abilities
+pub struct StatAssignment {
+    user_id: u64,
+    rolls_str: String,
+    // the rolled scores, highest first; each one is assigned in turn
+    scores: Vec<i32>,
+    assigned: [Option<i32>; 6],
+}
+
+#[derive(Default)]
+struct Assignments {
+    assignments: HashMap<u64, StatAssignment>,
+    // message ids, oldest first
+    order: VecDeque<u64>,
+}
+
+lazy_static! {
+    // Assignments in progress, by the id of the message whose components drive them
+    static ref ASSIGNMENTS: Mutex<Assignments> = Mutex::new(Assignments::default());
+}
+
+// How to respond to a click on one of the assignment message's components
+pub enum AssignmentReply {
+    // Edit the assignment message in place
+    Update(String, CreateComponents),
+    // Tell only the person who clicked
+    Private(String),
+}
+
+impl StatAssignment {
+    pub fn new(scores: &AbilityScores, user_id: u64, rolls_str: String) -> StatAssignment {
+        let mut sorted = scores.scores.clone();
+        sorted.sort_unstable_by(|a, b| b.cmp(a));
+        // 3d6 in order has already decided which score goes where
+        let assigned = if scores.method == GenerationMethod::ThreeInOrder {
+            [0, 1, 2, 3, 4, 5].map(|i| Some(scores.scores[i]))
+        } else {
+            [None; 6]
+        };
+        StatAssignment {
+            user_id,
+            rolls_str,
+            scores: sorted,
+            assigned,
+        }
+    }
+
+    fn next_score(&self) -> Option<i32> {
+        let num_assigned = self.assigned.iter().filter(|score| score.is_some()).count();
+        self.scores.get(num_assigned).copied()
+    }
+
+    pub fn content(&self) -> String {
+        match self.next_score() {
+            Some(score) => format!("{}\n\nAssign **{score}** to:", self.rolls_str),
+            None => format!("{}\n\nChoose your racial or background bonuses:", self.rolls_str),
+        }
+    }
+
+    pub fn components(&self) -> CreateComponents {
+        let mut components = CreateComponents::default();
+        if self.next_score().is_some() {
+            // 5 buttons fit on a row, so the sixth ability shares a row with the reset button
+            for row in ABILITIES.chunks(5) {
+                components.create_action_row(|action_row| {
+                    for ability in row {
+                        let i = ABILITIES.iter().position(|a| a == ability).unwrap();
+                        action_row.create_button(|button| match self.assigned[i] {
+                            Some(score) => button
+                                .style(ButtonStyle::Secondary)
+                                .label(format!("{ability} {score}"))
+                                .custom_id(format!("{ASSIGN_PREFIX}{ability}"))
+                                .disabled(true),
+                            None => button
+                                .style(ButtonStyle::Primary)
+                                .label(ability)
+                                .custom_id(format!("{ASSIGN_PREFIX}{ability}")),
+                        });
+                    }
+                    if row.len() < 5 {
+                        action_row.create_button(|button| {
+                            button.style(ButtonStyle::Danger).label("Start over").custom_id(RESET_ID)
+                        });
+                    }
+                    action_row
+                });
+            }
+        } else {
+            components.create_action_row(|action_row| {
+                action_row.create_select_menu(|menu| {
+                    menu.custom_id(RACE_ID).placeholder("Racial or background bonuses").options(|options| {
+                        for race in RACES {
+                            options.create_option(|option| {
+                                option.label(race.name()).value(race.name()).description(race.description())
+                            });
+                        }
+                        options
+                    })
+                })
+            });
+        }
+        components
+    }
+
+    fn final_block(&self, race: Race) -> String {
+        let base: [i32; 6] = self.assigned.map(|score| score.unwrap_or(0));
+        let bonuses = race.bonuses(&base);
+        let abilities_str = (0..6)
+            .map(|i| {
+                // racial increases can't take a score above 20
+                let score = (base[i] + bonuses[i]).min(20);
+                let modifier = format_ability_modifier(ability_modifier(score));
+                format!("{} **{score}** ({modifier})", ABILITIES[i])
+            })
+            .collect::<Vec<String>>()
+            .chunks(3)
+            .map(|line| line.join(" · "))
+            .collect::<Vec<String>>()
+            .join("\n");
+        let total_modifier: i32 = (0..6).map(|i| ability_modifier((base[i] + bonuses[i]).min(20))).sum();
+        format!(
+            "{}\n\n**Final ability scores** ({}):\n{abilities_str}\nTotal modifier: **{}**",
+            self.rolls_str,
+            race.name(),
+            format_ability_modifier(total_modifier)
+        )
+    }
+}
+
+// Start tracking an assignment once its message has been sent
+pub fn start_assignment(message_id: u64, assignment: StatAssignment) {
+    let mut stored = ASSIGNMENTS.lock().unwrap();
+    if stored.assignments.insert(message_id, assignment).is_none() {
+        stored.order.push_back(message_id);
+    }
+    while stored.order.len() > MAX_ASSIGNMENTS {
+        if let Some(oldest) = stored.order.pop_front() {
+            stored.assignments.remove(&oldest);
+        }
+    }
+}
+
+// Handle a click on an assignment button or a choice in the race menu
+pub fn handle_assignment_component(message_id: u64, user_id: u64, custom_id: &str, values: &[String]) -> AssignmentReply {
+    let mut stored = ASSIGNMENTS.lock().unwrap();
+    let assignment = match stored.assignments.get_mut(&message_id) {
+        Some(assignment) => assignment,
+        None => return AssignmentReply::Private("This character has expired. Roll a new one with `roll character`.".to_owned()),
+    };
+    if assignment.user_id != user_id {
+        return AssignmentReply::Private("Only the player who rolled this character can assign its scores.".to_owned());
+    }
+
+    if custom_id == RESET_ID {
+        assignment.assigned = [None; 6];
+    } else if let Some(ability) = custom_id.strip_prefix(ASSIGN_PREFIX) {
+        if let (Some(i), Some(score)) = (ABILITIES.iter().position(|a| *a == ability), assignment.next_score()) {
+            if assignment.assigned[i].is_none() {
+                assignment.assigned[i] = Some(score);
+            }
+        }
+    } else if custom_id == RACE_ID {
+        if let Some(race) = values.first().and_then(|name| Race::from_name(name)) {
+            let final_block = assignment.final_block(race);
+            stored.assignments.remove(&message_id);
+            stored.order.retain(|id| *id != message_id);
+            return AssignmentReply::Update(final_block, CreateComponents::default());
+        }
+    }
+    AssignmentReply::Update(assignment.content(), assignment.components())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::stat_assignment::{handle_assignment_component, start_assignment, AssignmentReply, Race, StatAssignment, MAX_ASSIGNMENTS, RESET_ID};
+
+    #[test]
+    fn fixed_racial_bonuses() {
+        let scores = [15, 14, 13, 12, 10, 8];
+        assert_eq!(Race::HalfOrc.bonuses(&scores), [2, 0, 1, 0, 0, 0]);
+        assert_eq!(Race::Human.bonuses(&scores), [1; 6]);
+    }
+    #[test]
+    fn flexible_bonuses_go_to_highest_scores() {
+        let scores = [8, 15, 10, 12, 14, 13];
+        assert_eq!(Race::Flexible.bonuses(&scores), [0, 2, 0, 0, 1, 0]);
+        // Half-Elves get CHA +2 on top, so the +1s skip CHA
+        assert_eq!(Race::HalfElf.bonuses(&[8, 10, 10, 12, 14, 15]), [0, 0, 0, 1, 1, 2]);
+    }
+    #[test]
+    fn oldest_assignments_expire() {
+        let assignment = || StatAssignment {
+            user_id: 1,
+            rolls_str: "".to_owned(),
+            scores: vec![15, 14, 13, 12, 10, 8],
+            assigned: [None; 6],
+        };
+        let first_id = 1 << 40;
+        for message_id in first_id..=first_id + MAX_ASSIGNMENTS as u64 {
+            start_assignment(message_id, assignment());
+        }
+        assert!(matches!(
+            handle_assignment_component(first_id, 1, RESET_ID, &[]),
+            AssignmentReply::Private(message) if message.starts_with("This character has expired.")
+        ));
+        assert!(matches!(
+            handle_assignment_component(first_id + 1, 1, RESET_ID, &[]),
+            AssignmentReply::Update(..)
+        ));
+    }
+}
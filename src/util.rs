@@ -1,4 +1,10 @@
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BinaryHeap},
+};
+
 use rand::Rng;
+use regex::{Captures, Match};
 
 lazy_static! {
     static ref CIRCLED_NUMS: Vec<char> =
@@ -17,14 +23,33 @@ fn get_circled_number(num: i32) -> String {
     }
 }
 
+// Format a flat modifier for display after the dice, e.g. " + 3" or " – 2" (empty if zero)
+pub fn format_modifier(modifier: i64) -> String {
+    let modifier_abs = modifier.abs();
+    if modifier > 0 {
+        format!(" + {modifier}")
+    } else if modifier < 0 {
+        format!(" – {modifier_abs}")
+    } else {
+        "".into()
+    }
+}
+
 pub fn format_roll(roll: &Roll, strikethrough: bool) -> String {
     let value = get_circled_number(roll.value);
-    if let Some(old_value) = roll.old_value {
-        let old_value = get_circled_number(old_value);
+    // faces that were replaced, either by rerolling for a duplicate, by rerolling or by clamping
+    let old_values: String = roll
+        .duplicate_values
+        .iter()
+        .chain(roll.old_value.iter())
+        .chain(roll.unclamped_value.iter())
+        .map(|old_value| get_circled_number(*old_value))
+        .collect();
+    if !old_values.is_empty() {
         if strikethrough {
-            format!("~~{old_value}{value}~~")
+            format!("~~{old_values}{value}~~")
         } else {
-            format!("~~{old_value}~~{value}")
+            format!("~~{old_values}~~{value}")
         }
     } else {
         if strikethrough {
@@ -35,10 +60,44 @@ pub fn format_roll(roll: &Roll, strikethrough: bool) -> String {
     }
 }
 
+// Summarize a pool of dice by how many times each face came up, highest face first,
+// e.g. "6×⑥, 4×⑤, 1×①"
+pub fn format_roll_summary(rolls: &[&Roll], strikethrough: bool) -> String {
+    let mut face_counts: BTreeMap<i32, usize> = BTreeMap::new();
+    for roll in rolls {
+        *face_counts.entry(roll.value).or_insert(0) += 1;
+    }
+    let summary = face_counts
+        .iter()
+        .rev()
+        .map(|(face, count)| format!("{count}×{}", get_circled_number(*face)))
+        .collect::<Vec<String>>()
+        .join(", ");
+    if strikethrough {
+        format!("~~{summary}~~")
+    } else {
+        summary
+    }
+}
+
 #[derive(Clone, Eq, PartialEq)]
 pub struct Roll {
     pub value: i32, // the roll's final value after any rerolling, or initial value if it wasn't rerolled
     pub old_value: Option<i32>, // the dice's original value (only Some() if it was rerolled, else None)
+    pub unclamped_value: Option<i32>, // the value before applying a min/max clamp (only Some() if it was clamped, else None)
+    pub duplicate_values: Vec<i32>, // for unique rolls, every face rolled before this one that duplicated an earlier die
+}
+
+impl Roll {
+    // A die that landed on value and was left alone
+    pub fn new(value: i32) -> Roll {
+        Roll {
+            value,
+            old_value: None,
+            unclamped_value: None,
+            duplicate_values: vec![],
+        }
+    }
 }
 
 impl Ord for Roll {
@@ -58,17 +117,211 @@ pub fn roll(dice_size: i32, reroll_if_less_than_or_equal_to: i32) -> Roll {
     if roll1 <= reroll_if_less_than_or_equal_to {
         let replacement_roll = rand::thread_rng().gen_range(1..=dice_size);
         Roll {
-            value: replacement_roll,
             old_value: Some(roll1),
+            ..Roll::new(replacement_roll)
         }
+    } else {
+        Roll::new(roll1)
+    }
+}
+
+// Roll an exploding ("acing") die: whenever it lands on its highest face, roll it again and add the result.
+// Returns the total along with every roll in the chain.
+// (A chain is capped at 100 rolls, and a d1 can't explode at all, so this always ends)
+pub fn roll_exploding(dice_size: i32) -> (i64, Vec<Roll>) {
+    let mut rolls = vec![roll(dice_size, 0)];
+    while dice_size > 1 && rolls.last().unwrap().value == dice_size && rolls.len() < 100 {
+        rolls.push(roll(dice_size, 0));
+    }
+    let total = rolls.iter().map(|roll| roll.value as i64).sum();
+    (total, rolls)
+}
+
+// Clamp a roll's value to at least min and at most max (e.g. Reliable Talent treating
+// anything below 10 as a 10), remembering the original face so it can be shown struck through
+pub fn clamp_roll(roll: Roll, min: Option<i32>, max: Option<i32>) -> Roll {
+    let clamped = match (min, max) {
+        (Some(min), _) if roll.value < min => min,
+        (_, Some(max)) if roll.value > max => max,
+        _ => roll.value,
+    };
+    if clamped == roll.value {
+        roll
     } else {
         Roll {
-            value: roll1,
-            old_value: None,
+            value: clamped,
+            unclamped_value: Some(roll.value),
+            ..roll
         }
     }
 }
 
+// Parse a regex capture group as a number, falling back to the default
+// if the group didn't participate in the match (or isn't a valid number)
+pub fn parse(group_match: Option<Match>, default: i32) -> i32 {
+    match group_match {
+        Some(group_match) => group_match.as_str().parse::<i32>().unwrap_or(default),
+        None => default,
+    }
+}
+
+// The most dice a single roll can have
+pub const MAX_DICE: i32 = 1000;
+
+pub fn too_many_dice_error() -> String {
+    format!("Can't roll more than {MAX_DICE} dice at once.")
+}
+
+// The reply for a number that doesn't fit in an i32, e.g. as found by find_oversized_number
+pub fn too_big_number_error(number: &str) -> String {
+    format!("`{number}` is too big a number to roll with.")
+}
+
+// Find the first captured number that doesn't fit in an i32, if any,
+// so it can be reported to the user instead of silently falling back to a default
+pub fn find_oversized_number<'t>(groups: &Captures<'t>) -> Option<&'t str> {
+    groups
+        .iter()
+        .skip(1) // the whole match
+        .flatten()
+        .map(|group_match| group_match.as_str())
+        .find(|text| {
+            let digits = text.trim_start_matches(['+', '-']);
+            !digits.is_empty()
+                && digits.chars().all(|c| c.is_ascii_digit())
+                && text.parse::<i32>().is_err()
+        })
+}
+
+// Roll something num_rolls times and keep the highest (MarkCondition::Highest, i.e. advantage)
+// or the lowest (MarkCondition::Lowest, i.e. disadvantage) value.
+// roll_once returns a value along with how to display it (e.g. a single die or a whole pool).
+// Returns the kept value along with a string showing every roll, the kept one in bold
+pub fn best_of<T, F>(num_rolls: i32, condition: MarkCondition, mut roll_once: F) -> (T, String)
+where
+    T: Ord + Copy,
+    F: FnMut() -> (T, String),
+{
+    let rolls: Vec<(T, String)> = (0..num_rolls.max(1)).map(|_| roll_once()).collect();
+    keep_best(&rolls, condition)
+}
+
+// Like best_of, for rolls that have already been made (there must be at least one)
+pub fn keep_best<T: Ord + Copy>(rolls: &[(T, String)], condition: MarkCondition) -> (T, String) {
+    let values: Vec<T> = rolls.iter().map(|(value, _)| *value).collect();
+    if rolls.len() == 1 || values.iter().all(|value| *value == values[0]) {
+        // nothing to choose between, so don't highlight any of them
+        let full_roll_str = rolls
+            .iter()
+            .map(|(_, roll_str)| roll_str.as_str())
+            .collect::<Vec<&str>>()
+            .join(" / ");
+        return (values[0], full_roll_str);
+    }
+
+    let marked = mark_rolls(&values, 1, condition);
+    let kept_value = values[marked.iter().position(|is_marked| *is_marked).unwrap()];
+    let full_roll_str = rolls
+        .iter()
+        .zip(marked.iter())
+        .map(|((_, roll_str), is_marked)| {
+            if *is_marked {
+                format!("**{roll_str}**")
+            } else {
+                roll_str.clone()
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(" / ");
+    (kept_value, full_roll_str)
+}
+
+// Roll num_rolls dice and keep the highest or lowest one, e.g. a d20 with advantage
+pub fn roll_with_advantage(dice_size: i32, reroll: i32, num_rolls: i32, condition: MarkCondition) -> (i32, String) {
+    best_of(num_rolls, condition, || {
+        let roll = roll(dice_size, reroll);
+        (roll.value, format_roll(&roll, false))
+    })
+}
+
+// A comparison against a target number, e.g. the `>=15` in `count>=15`
+#[derive(Copy, Clone)]
+pub enum Comparison {
+    AtLeast,
+    AtMost,
+    GreaterThan,
+    LessThan,
+    EqualTo,
+}
+
+impl Comparison {
+    // Parse an operator such as ">=" or "<"
+    pub fn from_operator(operator: &str) -> Option<Comparison> {
+        match operator {
+            ">=" | "=>" => Some(Comparison::AtLeast),
+            "<=" | "=<" => Some(Comparison::AtMost),
+            ">" => Some(Comparison::GreaterThan),
+            "<" => Some(Comparison::LessThan),
+            "=" | "==" => Some(Comparison::EqualTo),
+            _ => None,
+        }
+    }
+
+    pub fn matches(self, value: i64, target: i64) -> bool {
+        match self {
+            Comparison::AtLeast => value >= target,
+            Comparison::AtMost => value <= target,
+            Comparison::GreaterThan => value > target,
+            Comparison::LessThan => value < target,
+            Comparison::EqualTo => value == target,
+        }
+    }
+
+    pub fn symbol(self) -> &'static str {
+        match self {
+            Comparison::AtLeast => "≥",
+            Comparison::AtMost => "≤",
+            Comparison::GreaterThan => ">",
+            Comparison::LessThan => "<",
+            Comparison::EqualTo => "=",
+        }
+    }
+}
+
+// Roll a d100 as a tens die (00–90) and a units die (0–9), where 00 and 0 make 100.
+// Positive bonus_dice roll that many extra tens dice and keep the lowest result (Call of Cthulhu bonus dice),
+// negative bonus_dice do the same but keep the highest (penalty dice).
+// Returns the total along with the tens dice (the kept one in bold) and the units die
+pub fn roll_percentile(bonus_dice: i32) -> (i32, String) {
+    // 1d10 - 1 gives the 0–9 faces of a percentile die
+    let units = roll(10, 0).value - 1;
+    let condition = if bonus_dice > 0 {
+        MarkCondition::Lowest
+    } else {
+        MarkCondition::Highest
+    };
+    let (value, tens_str) = best_of(bonus_dice.abs() + 1, condition, || {
+        let tens = roll(10, 0).value - 1;
+        let value = match tens * 10 + units {
+            0 => 100,
+            value => value,
+        };
+        let tens_str = format!("{}⓪", CIRCLED_NUMS[tens as usize]);
+        (value, tens_str)
+    });
+    let units_str = CIRCLED_NUMS[units as usize];
+    (value, format!("{tens_str} + {units_str}"))
+}
+
+// How a roll turned out, for rolls that can succeed or fail
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    CriticalSuccess,
+    Success,
+    Failure,
+    CriticalFailure,
+}
+
 #[derive(Copy, Clone)]
 pub enum MarkCondition {
     Highest,
@@ -80,80 +333,86 @@ pub enum MarkCondition {
 // Marks the highest or lowest num_to_mark rolls, depending on the supplied condition.
 // Essentially, this is a min/max function but with the biggest/smallest num_to_mark rolls
 // (instead of just the single biggest/smallest)
-// PRECONDITION: num_to_mark <= rolls.length - 1
-pub fn mark_rolls<T: Ord>(rolls: &Vec<T>, num_to_mark: i32, condition: MarkCondition) -> Vec<bool> {
-    // A working vector of the num_to_mark best rolls.
-    // "Best" means highest or lowest, corresponding to the condition
-    let mut best: Vec<(usize, &T)> = rolls[0..num_to_mark as usize]
-        .iter()
-        .enumerate()
-        .clone()
-        .collect();
-  
-    // Of the rolls in the vector above, which is the worst?
-    // E.g. if condition is highest and the best so far are 13, 11, and 15,
-    // 11 is the worst of the best
-    let mut worst_of_the_best = match condition {
+// Ties break the way the original scan always has (see below).
+// num_to_mark may be anything; zero (or less) marks nothing and rolls.len() (or more) marks everything.
+pub fn mark_rolls<T: Ord>(rolls: &[T], num_to_mark: i32, condition: MarkCondition) -> Vec<bool> {
+    let num_to_mark = (num_to_mark.max(0) as usize).min(rolls.len());
+    let mut result = vec![false; rolls.len()];
+    if num_to_mark == 0 {
+        return result;
+    }
+
+    // Scan the rolls in order, keeping the best num_to_mark so far in fixed slots
+    // (starting with the first num_to_mark rolls). A roll strictly better than the worst of the best
+    // takes over that one's slot.
+    // The worst of the best sits at the top of a heap instead of being searched for on every replacement.
+    // The heap orders ties by slot to match that search: for Highest, the earliest slot of the lowest rolls
+    // is the worst, and for Lowest, the latest slot of the highest rolls is.
+    // Entries are (roll, slot, index into rolls)
+    match condition {
         MarkCondition::Highest => {
-            best.iter_mut().min_by(|x,y| x.1.cmp(y.1)).unwrap()
+            let mut best: BinaryHeap<Reverse<(&T, usize, usize)>> =
+                rolls[..num_to_mark].iter().enumerate().map(|(i, roll)| Reverse((roll, i, i))).collect();
+            for (i, roll) in rolls.iter().enumerate().skip(num_to_mark) {
+                let mut worst_of_the_best = best.peek_mut().unwrap();
+                if roll > worst_of_the_best.0 .0 {
+                    let slot = worst_of_the_best.0 .1;
+                    *worst_of_the_best = Reverse((roll, slot, i));
+                }
+            }
+            for Reverse((_, _, index)) in best {
+                result[index] = true;
+            }
         }
         MarkCondition::Lowest => {
-            best.iter_mut().max_by(|x,y| x.1.cmp(y.1)).unwrap()
-        }
-    };
-
-    // Now iterate through the input array.
-    // We skip the first num_to_mark elements because we already put them in the `best` vector
-    // (see `best` vector's initialization above)
-    for (i, roll) in rolls.iter().enumerate().skip(num_to_mark as usize) {
-        // If the roll is better than the worst of the best,
-        // we should yoink the worst of the best and replace it with this roll!
-        if matches!(condition, MarkCondition::Highest) && roll > worst_of_the_best.1
-        || matches!(condition, MarkCondition::Lowest) && roll < worst_of_the_best.1
-        {
-            // Kick out the old worst of the best:
-            // put this new roll where it was.
-            *worst_of_the_best = (i, roll);
-
-            // Now, we need to find out which of the best
-            // is now the worst.
-            // This is not necessarily the new roll.
-            // For instance, if the condition was Highest
-            // and we had 13, 11, and 15, and we kicked
-            // out 11 and replacd it with 14,
-            // the new one (14) is not the new worst of the best --
-            // 13 is!
-            match condition {
-                MarkCondition::Highest => {
-                    worst_of_the_best = best.iter_mut().min_by(|x,y| x.1.cmp(y.1)).unwrap();
-                }
-                MarkCondition::Lowest => {
-                    worst_of_the_best = best.iter_mut().max_by(|x,y| x.1.cmp(y.1)).unwrap();
+            let mut best: BinaryHeap<(&T, usize, usize)> =
+                rolls[..num_to_mark].iter().enumerate().map(|(i, roll)| (roll, i, i)).collect();
+            for (i, roll) in rolls.iter().enumerate().skip(num_to_mark) {
+                let mut worst_of_the_best = best.peek_mut().unwrap();
+                if roll < worst_of_the_best.0 {
+                    let slot = worst_of_the_best.1;
+                    *worst_of_the_best = (roll, slot, i);
                 }
             }
+            for (_, _, index) in best {
+                result[index] = true;
+            }
         }
     }
-    // Now we have a vector of the best rolls.
-    // However, we want to return a vector that gives info on 
-    // all rolls (e.g. booleans that tell whether they are
-    // one of the best ("marked") or not.
-    // This is because this format is easier to iterate though
-    // since it's ordered by index!
-    let result = {
-        let mut tmp = vec![false; (*rolls).len()];
-        for (index, _roll) in best {
-            tmp[index] = true
-        }
-        tmp
-    };
-
     result
 }
 
 
 #[cfg(test)]
 mod tests {
-    use crate::util::{MarkCondition, mark_rolls};
+    use regex::Regex;
+
+    use rand::Rng;
+
+    use crate::util::{MarkCondition, Roll, find_oversized_number, format_roll, mark_rolls, roll_exploding};
+
+    // The original mark_rolls, which rescans the best rolls so far for every replacement
+    fn rescanning_mark_rolls(rolls: &[i32], num_to_mark: usize, condition: MarkCondition) -> Vec<bool> {
+        let mut best: Vec<(usize, &i32)> = rolls[0..num_to_mark].iter().enumerate().collect();
+        let find_worst = |best: &[(usize, &i32)]| match condition {
+            MarkCondition::Highest => best.iter().enumerate().min_by(|x, y| x.1 .1.cmp(y.1 .1)).unwrap().0,
+            MarkCondition::Lowest => best.iter().enumerate().max_by(|x, y| x.1 .1.cmp(y.1 .1)).unwrap().0,
+        };
+        let mut worst = find_worst(&best);
+        for (i, roll) in rolls.iter().enumerate().skip(num_to_mark) {
+            if matches!(condition, MarkCondition::Highest) && roll > best[worst].1
+                || matches!(condition, MarkCondition::Lowest) && roll < best[worst].1
+            {
+                best[worst] = (i, roll);
+                worst = find_worst(&best);
+            }
+        }
+        let mut result = vec![false; rolls.len()];
+        for (index, _roll) in best {
+            result[index] = true;
+        }
+        result
+    }
 
     #[test]
     fn highest_3() {
@@ -218,4 +477,79 @@ mod tests {
             vec![false, false, false, false, false, true, true, true]);
 
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn oversized_number() {
+        let re = Regex::new(r"^(?P<num_dice>\d+)d(?P<dice_size>\d+)(?P<modifier>[+-]\d+)?$").unwrap();
+        let groups = re.captures("1d20+99999999999").unwrap();
+        assert_eq!(find_oversized_number(&groups), Some("+99999999999"));
+    }
+    #[test]
+    fn exploding_d1_does_not_explode() {
+        let (total, rolls) = roll_exploding(1);
+        assert_eq!(total, 1);
+        assert_eq!(rolls.len(), 1);
+    }
+    #[test]
+    fn duplicates_come_before_rerolls() {
+        let roll = Roll {
+            old_value: Some(1),
+            duplicate_values: vec![3, 2],
+            ..Roll::new(4)
+        };
+        assert_eq!(format_roll(&roll, false), "~~③②①~~④");
+    }
+    #[test]
+    fn no_oversized_number() {
+        let re = Regex::new(r"^(?P<num_dice>\d+)d(?P<dice_size>\d+)(?P<modifier>[+-]\d+)?$").unwrap();
+        let groups = re.captures("1d2147483647-2147483648").unwrap();
+        assert_eq!(find_oversized_number(&groups), None);
+    }
+
+    #[test]
+    fn highest_ties() {
+        let input = vec![2,2,5,6];
+        assert_eq!(mark_rolls(&input, 3, MarkCondition::Highest),
+            vec![false, true, true, true]);
+    }
+    #[test]
+    fn highest_ties_by_slot() {
+        // the second 2 takes the 1's slot, which comes before the first 2's, so it's the one the 9 replaces
+        let input = vec![1,2,2,9];
+        assert_eq!(mark_rolls(&input, 2, MarkCondition::Highest),
+            vec![false, true, false, true]);
+    }
+    #[test]
+    fn lowest_ties() {
+        let input = vec![4,1,4,4];
+        assert_eq!(mark_rolls(&input, 2, MarkCondition::Lowest),
+            vec![true, true, false, false]);
+    }
+    #[test]
+    fn same_marks_as_rescanning() {
+        let mut rng = rand::thread_rng();
+        for _ in 0..2000 {
+            let len = rng.gen_range(1..=12);
+            // few faces, so that there are plenty of ties
+            let rolls: Vec<i32> = (0..len).map(|_| rng.gen_range(1..=4)).collect();
+            for num_to_mark in 1..=len {
+                for condition in [MarkCondition::Highest, MarkCondition::Lowest] {
+                    assert_eq!(mark_rolls(&rolls, num_to_mark as i32, condition),
+                        rescanning_mark_rolls(&rolls, num_to_mark, condition), "{rolls:?} {num_to_mark}");
+                }
+            }
+        }
+    }
+    #[test]
+    fn mark_none() {
+        let input = vec![3,1,2];
+        assert_eq!(mark_rolls(&input, 0, MarkCondition::Highest),
+            vec![false, false, false]);
+    }
+    #[test]
+    fn mark_all() {
+        let input = vec![3,1,2];
+        assert_eq!(mark_rolls(&input, 3, MarkCondition::Lowest),
+            vec![true, true, true]);
+    }
+}